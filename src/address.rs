@@ -0,0 +1,169 @@
+use std::fmt;
+
+/// Characters that force a display name to be quoted (RFC 5322 `specials`).
+const SPECIALS: &[char] = &[
+    '(', ')', '<', '>', '[', ']', ':', ';', '@', '\\', ',', '.', '"',
+];
+
+/// A recipient such as `"山田 太郎" <yamada@example.jp>` or `yamada@example.jp`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mailbox {
+    pub name: Option<String>,
+    /// The address with its domain lowercased and IDN labels in punycode.
+    pub email: String,
+}
+
+impl Mailbox {
+    pub fn parse(input: &str) -> anyhow::Result<Self> {
+        let input = input.trim();
+        let (name, address) = match input.strip_suffix('>').and_then(|s| s.rsplit_once('<')) {
+            Some((name, address)) => (parse_display_name(name)?, address.trim()),
+            None => (None, input),
+        };
+
+        let (local, domain) = address
+            .rsplit_once('@')
+            .ok_or_else(|| anyhow::anyhow!("Missing '@' in address: {}", input))?;
+        let domain = idna::domain_to_ascii(domain.trim_end_matches('.'))
+            .map_err(|_| anyhow::anyhow!("Invalid domain in address: {}", input))?;
+        let email = lettre::Address::new(local, &domain)
+            .map_err(|e| anyhow::anyhow!("Invalid address '{}': {}", input, e))?;
+        if !domain.contains('.') {
+            return Err(anyhow::anyhow!(
+                "Invalid address '{}': domain has no dot",
+                input
+            ));
+        }
+
+        Ok(Self {
+            name,
+            email: email.to_string(),
+        })
+    }
+
+    pub fn domain(&self) -> &str {
+        self.email
+            .rsplit_once('@')
+            .map(|(_, domain)| domain)
+            .unwrap_or("")
+    }
+}
+
+impl fmt::Display for Mailbox {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            Some(name) if name.contains(SPECIALS) => write!(
+                f,
+                "\"{}\" <{}>",
+                name.replace('\\', "\\\\").replace('"', "\\\""),
+                self.email
+            ),
+            Some(name) => write!(f, "{} <{}>", name, self.email),
+            None => write!(f, "{}", self.email),
+        }
+    }
+}
+
+/// Unquotes `"..."` display names and collapses whitespace. Empty names are `None`.
+fn parse_display_name(raw: &str) -> anyhow::Result<Option<String>> {
+    let raw = raw.trim();
+    let name = match raw.strip_prefix('"') {
+        Some(quoted) => {
+            let quoted = quoted
+                .strip_suffix('"')
+                .ok_or_else(|| anyhow::anyhow!("Unterminated quote in display name: {}", raw))?;
+            let mut name = String::new();
+            let mut chars = quoted.chars();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => name.extend(chars.next()),
+                    c => name.push(c),
+                }
+            }
+            name
+        }
+        None if raw.contains(['<', '>', '"']) => {
+            return Err(anyhow::anyhow!("Invalid display name: {}", raw));
+        }
+        None => raw.to_string(),
+    };
+    let name = name.split_whitespace().collect::<Vec<_>>().join(" ");
+    Ok((!name.is_empty()).then_some(name))
+}
+
+/// Bare address of a recipient, falling back to the input when it does not parse.
+pub fn email_of(recipient: &str) -> String {
+    Mailbox::parse(recipient)
+        .map(|mailbox| mailbox.email)
+        .unwrap_or_else(|_| recipient.trim().to_string())
+}
+
+/// Parses and normalizes every address, reporting all invalid ones at once.
+pub fn normalize(recipients: &[String]) -> anyhow::Result<Vec<String>> {
+    let mut normalized = Vec::with_capacity(recipients.len());
+    let mut errors = Vec::new();
+    for recipient in recipients {
+        match Mailbox::parse(recipient) {
+            Ok(mailbox) => normalized.push(mailbox.to_string()),
+            Err(e) => errors.push(e.to_string()),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(anyhow::anyhow!("{}", errors.join("\n")));
+    }
+    Ok(normalized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_name_and_idn() -> anyhow::Result<()> {
+        let mailbox = Mailbox::parse("\"山田 太郎\" <yamada@Example.JP>")?;
+        assert_eq!(mailbox.name.as_deref(), Some("山田 太郎"));
+        assert_eq!(mailbox.email, "yamada@example.jp");
+        assert_eq!(mailbox.to_string(), "山田 太郎 <yamada@example.jp>");
+
+        let mailbox = Mailbox::parse("  info@日本語.jp ")?;
+        assert_eq!(mailbox.email, "info@xn--wgv71a119e.jp");
+        assert_eq!(mailbox.domain(), "xn--wgv71a119e.jp");
+
+        let mailbox = Mailbox::parse("\"Doe, John\" <john@example.com>")?;
+        assert_eq!(mailbox.to_string(), "\"Doe, John\" <john@example.com>");
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_addresses() {
+        for input in [
+            "yamada",
+            "yamada@",
+            "@example.jp",
+            "yamada@@example.jp",
+            "yamada@localhost",
+            "Yamada <yamada@example.jp",
+            "\"Yamada <yamada@example.jp>",
+            "two words@example.jp",
+        ] {
+            assert!(
+                Mailbox::parse(input).is_err(),
+                "{} should be rejected",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_normalize_reports_every_error() {
+        let error = normalize(&[
+            "a@example.com".to_string(),
+            "broken".to_string(),
+            "also broken@".to_string(),
+        ])
+        .unwrap_err()
+        .to_string();
+        assert_eq!(error.lines().count(), 2);
+        assert_eq!(email_of("Bob <BOB@Example.com>"), "BOB@example.com");
+    }
+}
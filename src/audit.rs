@@ -0,0 +1,331 @@
+use crate::history::{LogEntry, TransferRecord};
+use anyhow::Context;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Number of records and hash of the last one, stored next to the log so that
+/// truncating the tail of the log can be detected.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Head {
+    pub count: usize,
+    pub hash: String,
+    /// Number of records written before the audit key was created; every later one has an HMAC.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keyed_from: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hmac: Option<String>,
+}
+
+impl Head {
+    /// Head of `entries`, authenticated with `key` when there is one.
+    pub fn new(entries: &[LogEntry], keyed_from: Option<usize>, key: Option<&[u8]>) -> Self {
+        let mut head = Self {
+            count: entries.len(),
+            hash: entries
+                .last()
+                .map(|entry| entry.hash.clone())
+                .unwrap_or_else(|| GENESIS_HASH.to_string()),
+            keyed_from,
+            hmac: None,
+        };
+        head.hmac = key.map(|key| record_hmac(key, &head.signed_data()));
+        head
+    }
+
+    /// Heads written before `keyed_from` existed only authenticate the hash.
+    fn signed_data(&self) -> String {
+        match self.keyed_from {
+            Some(keyed_from) => format!("{}:{}:{}", self.count, self.hash, keyed_from),
+            None => self.hash.clone(),
+        }
+    }
+
+    /// Where HMACs start, also for logs whose head predates `keyed_from`.
+    pub fn keyed_from(head: Option<&Head>, entries: &[LogEntry]) -> usize {
+        head.and_then(|head| head.keyed_from).unwrap_or_else(|| {
+            entries
+                .iter()
+                .position(|entry| entry.hmac.is_some())
+                .unwrap_or(entries.len())
+        })
+    }
+}
+
+pub fn record_hash(prev_hash: &str, record: &TransferRecord) -> anyhow::Result<String> {
+    let mut hasher = Sha256::new();
+    hasher.update(prev_hash.as_bytes());
+    hasher.update(serde_json::to_vec(record)?);
+    Ok(hex::encode(hasher.finalize()))
+}
+
+pub fn record_hmac(key: &[u8], hash: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(hash.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+fn hmac_matches(key: &[u8], hash: &str, expected: &str) -> bool {
+    let Ok(expected) = hex::decode(expected) else {
+        return false;
+    };
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(hash.as_bytes());
+    mac.verify_slice(&expected).is_ok()
+}
+
+pub fn head_path(log_path: &Path) -> PathBuf {
+    log_path.with_extension("head")
+}
+
+pub fn key_path() -> PathBuf {
+    std::env::var_os("PPAP_AUDIT_KEY")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| crate::config::config_dir().join("audit.key"))
+}
+
+pub fn load_key(path: &Path) -> anyhow::Result<Option<Vec<u8>>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read audit key: {}", path.display()))?;
+    let key = hex::decode(content.trim())
+        .with_context(|| format!("Invalid audit key: {}", path.display()))?;
+    Ok(Some(key))
+}
+
+pub fn generate_key(path: &Path) -> anyhow::Result<()> {
+    if path.exists() {
+        return Err(anyhow::anyhow!(
+            "Audit key already exists: {}",
+            path.display()
+        ));
+    }
+    let mut key = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut key);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    crate::credentials::write_private(path, hex::encode(key).as_bytes())
+        .with_context(|| format!("Failed to write audit key: {}", path.display()))
+}
+
+pub fn read_head(log_path: &Path) -> anyhow::Result<Option<Head>> {
+    let path = head_path(log_path);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let content = std::fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read audit head: {}", path.display()))?;
+    Ok(Some(serde_json::from_str(&content)?))
+}
+
+pub fn write_head(log_path: &Path, head: &Head) -> anyhow::Result<()> {
+    let path = head_path(log_path);
+    let temp = path.with_extension("head.tmp");
+    std::fs::write(&temp, serde_json::to_string(head)?)?;
+    std::fs::rename(&temp, &path)
+        .with_context(|| format!("Failed to write audit head: {}", path.display()))
+}
+
+/// Checks the chain and returns a description of every problem found.
+pub fn verify(entries: &[LogEntry], head: Option<&Head>, key: Option<&[u8]>) -> Vec<String> {
+    let mut problems = Vec::new();
+    let mut prev_hash = GENESIS_HASH.to_string();
+    let keyed_from = Head::keyed_from(head, entries);
+
+    for (i, entry) in entries.iter().enumerate() {
+        let n = i + 1;
+        if entry.prev_hash != prev_hash {
+            problems.push(format!(
+                "record {}: previous hash does not match, a record before it was removed or altered",
+                n
+            ));
+        }
+        match record_hash(&entry.prev_hash, &entry.record) {
+            Ok(hash) if hash == entry.hash => {}
+            _ => problems.push(format!("record {}: content does not match its hash", n)),
+        }
+
+        match (&entry.hmac, key) {
+            (Some(hmac), Some(key)) if !hmac_matches(key, &entry.hash, hmac) => {
+                problems.push(format!("record {}: HMAC does not match", n));
+            }
+            (None, Some(_)) if i >= keyed_from => {
+                problems.push(format!("record {}: HMAC is missing", n));
+            }
+            _ => {}
+        }
+        prev_hash = entry.hash.clone();
+    }
+
+    match head {
+        Some(head) => {
+            if head.count != entries.len() || head.hash != prev_hash {
+                problems.push(format!(
+                    "log has {} record(s) but the head expects {}, records were removed or appended outside ppap",
+                    entries.len(),
+                    head.count
+                ));
+            }
+            match (&head.hmac, key) {
+                (Some(hmac), Some(key)) if !hmac_matches(key, &head.signed_data(), hmac) => {
+                    problems.push("head: HMAC does not match".to_string());
+                }
+                (None, Some(_)) => problems.push(
+                    "head: HMAC is missing, the log was rewritten without the audit key"
+                        .to_string(),
+                ),
+                _ => {}
+            }
+        }
+        None if !entries.is_empty() => problems.push("head file is missing".to_string()),
+        None => {}
+    }
+
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::{self, FileDigest, Outcome};
+    use tempfile::tempdir;
+
+    fn record(recipient: &str) -> TransferRecord {
+        TransferRecord {
+            timestamp: "2026-01-01T00:00:00Z".parse().unwrap(),
+            recipients: vec![recipient.to_string()],
+            files: vec![FileDigest {
+                name: "report.pdf".to_string(),
+                size: 3,
+                sha256: "abc".to_string(),
+            }],
+            cc: vec![],
+            password_recipients: vec![],
+            archive_size: 10,
+            message_ids: vec![],
+            reference: String::new(),
+            receipts: vec![],
+            secure: false,
+            outcome: Outcome::Delivered,
+            error: None,
+        }
+    }
+
+    fn write_log(path: &Path, key: Option<&[u8]>) -> anyhow::Result<()> {
+        for recipient in ["a@example.com", "b@example.com", "c@example.com"] {
+            history::append_with_key(path, &record(recipient), key)?;
+        }
+        Ok(())
+    }
+
+    fn verify_log(path: &Path, key: Option<&[u8]>) -> anyhow::Result<Vec<String>> {
+        let entries = history::read_entries(path)?;
+        Ok(verify(&entries, read_head(path)?.as_ref(), key))
+    }
+
+    #[test]
+    fn test_intact_log_verifies() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("history.jsonl");
+        write_log(&path, Some(b"secret"))?;
+
+        assert!(verify_log(&path, Some(b"secret"))?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_modified_record_is_detected() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("history.jsonl");
+        write_log(&path, None)?;
+
+        let content = std::fs::read_to_string(&path)?.replace("b@example.com", "x@example.com");
+        std::fs::write(&path, content)?;
+
+        let problems = verify_log(&path, None)?;
+        assert_eq!(problems, vec!["record 2: content does not match its hash"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_deleted_record_is_detected() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("history.jsonl");
+        write_log(&path, None)?;
+
+        let content = std::fs::read_to_string(&path)?;
+        let lines: Vec<&str> = content.lines().collect();
+
+        std::fs::write(&path, format!("{}\n{}\n", lines[0], lines[2]))?;
+        assert!(!verify_log(&path, None)?.is_empty());
+
+        std::fs::write(&path, format!("{}\n{}\n", lines[0], lines[1]))?;
+        assert!(!verify_log(&path, None)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_rehashed_log_fails_hmac() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("history.jsonl");
+        write_log(&path, Some(b"secret"))?;
+
+        // Rewriting the whole chain with a different key is caught by the HMAC.
+        let forged = temp_dir.path().join("forged.jsonl");
+        write_log(&forged, Some(b"attacker"))?;
+
+        assert!(verify_log(&path, Some(b"secret"))?.is_empty());
+        assert!(!verify_log(&forged, Some(b"secret"))?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_stripped_hmacs_are_detected() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("history.jsonl");
+        write_log(&path, Some(b"secret"))?;
+
+        // Dropping every HMAC and rebuilding the chain and head without the key.
+        let mut entries = history::read_entries(&path)?;
+        let mut prev_hash = GENESIS_HASH.to_string();
+        for entry in &mut entries {
+            entry.hmac = None;
+            entry.prev_hash = prev_hash;
+            entry.hash = record_hash(&entry.prev_hash, &entry.record)?;
+            prev_hash = entry.hash.clone();
+        }
+        let lines: Vec<String> = entries
+            .iter()
+            .map(serde_json::to_string)
+            .collect::<Result<_, _>>()?;
+        std::fs::write(&path, lines.join("\n") + "\n")?;
+        write_head(&path, &Head::new(&entries, None, None))?;
+
+        assert!(verify_log(&path, None)?.is_empty());
+        let problems = verify_log(&path, Some(b"secret"))?;
+        assert!(problems
+            .iter()
+            .any(|p| p.starts_with("head: HMAC is missing")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_created_after_first_records() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("history.jsonl");
+        history::append_with_key(&path, &record("a@example.com"), None)?;
+        history::seal(&path, b"secret")?;
+        history::append_with_key(&path, &record("b@example.com"), Some(b"secret"))?;
+
+        assert!(verify_log(&path, Some(b"secret"))?.is_empty());
+        assert_eq!(read_head(&path)?.unwrap().keyed_from, Some(1));
+        Ok(())
+    }
+}
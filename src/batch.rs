@@ -0,0 +1,444 @@
+use crate::address;
+use crate::email::sender::{self, EmailSender, Receipt};
+use crate::email::sendgrid::SendGridSender;
+use crate::history::{self, FileDigest, Outcome, TransferRecord};
+use crate::policy::{self, Envelope, Policy};
+use crate::template::{self, Templates};
+use crate::zip::{self, ArchiveFormat, PasswordPolicy};
+use anyhow::Context;
+use futures::StreamExt;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Values inside the `files` and `recipients` columns are separated by this character.
+const LIST_SEPARATOR: char = ';';
+
+/// Template variables ppap fills in itself, which a column must not shadow.
+const RESERVED_COLUMNS: [&str; 4] = ["password", "link", "expires", "reference"];
+
+#[derive(Debug)]
+pub struct Row {
+    pub number: usize,
+    pub files: Vec<String>,
+    pub recipients: Vec<String>,
+    /// Every other column, available as `{column}` in the templates.
+    pub vars: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RowResult {
+    pub row: usize,
+    pub recipients: String,
+    pub files: String,
+    pub status: Outcome,
+    pub reference: String,
+    pub message_ids: String,
+    /// SendGrid's `X-Message-Id`s, or the relay's queue IDs.
+    pub provider_message_ids: String,
+    pub error: String,
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(LIST_SEPARATOR)
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Reads the manifest. Relative file paths are resolved against the manifest's directory.
+pub fn read_manifest(path: &Path) -> anyhow::Result<Vec<Row>> {
+    let mut reader = csv::Reader::from_path(path)
+        .with_context(|| format!("Failed to open manifest: {}", path.display()))?;
+    let headers: Vec<String> = reader.headers()?.iter().map(str::to_string).collect();
+    if let Some(reserved) = headers
+        .iter()
+        .find(|h| RESERVED_COLUMNS.contains(&h.as_str()))
+    {
+        return Err(anyhow::anyhow!(
+            "Manifest column '{}' is reserved for the value ppap generates: {}",
+            reserved,
+            path.display()
+        ));
+    }
+    for required in ["files", "recipients"] {
+        if !headers.iter().any(|h| h == required) {
+            return Err(anyhow::anyhow!(
+                "Manifest is missing the '{}' column: {}",
+                required,
+                path.display()
+            ));
+        }
+    }
+    let base_dir = path.parent().unwrap_or(Path::new("."));
+
+    let mut rows = Vec::new();
+    for (i, record) in reader.records().enumerate() {
+        let number = i + 1;
+        let record = record.with_context(|| format!("Invalid manifest row {}", number))?;
+
+        let mut files = Vec::new();
+        let mut recipients = Vec::new();
+        let mut vars = BTreeMap::new();
+        for (header, value) in headers.iter().zip(record.iter()) {
+            match header.as_str() {
+                "files" => {
+                    files = split_list(value)
+                        .into_iter()
+                        .map(|f| base_dir.join(f).to_string_lossy().into_owned())
+                        .collect()
+                }
+                "recipients" => {
+                    recipients = address::normalize(&split_list(value))
+                        .with_context(|| format!("Invalid recipient in manifest row {}", number))?
+                }
+                _ => {
+                    vars.insert(header.clone(), value.to_string());
+                }
+            }
+        }
+
+        if files.is_empty() || recipients.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Manifest row {} needs at least one file and one recipient",
+                number
+            ));
+        }
+        rows.push(Row {
+            number,
+            files,
+            recipients,
+            vars,
+        });
+    }
+    Ok(rows)
+}
+
+pub fn write_results(path: &Path, results: &[RowResult]) -> anyhow::Result<()> {
+    let mut writer = csv::Writer::from_path(path)
+        .with_context(|| format!("Failed to create results file: {}", path.display()))?;
+    for result in results {
+        writer.serialize(result)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn default_results_path(manifest: &Path) -> PathBuf {
+    let stem = manifest
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("manifest");
+    manifest.with_file_name(format!("{}.results.csv", stem))
+}
+
+/// Sends every row with at most `concurrency` rows in flight. Results are in row order.
+/// Rows to first-time recipients only pass a confirming send policy with `assume_yes`.
+pub async fn run(
+    rows: Vec<Row>,
+    sender: &SendGridSender,
+    templates: &Templates,
+    password_policy: &PasswordPolicy,
+    send_policy: &Policy,
+    assume_yes: bool,
+    concurrency: usize,
+) -> Vec<RowResult> {
+    let mut results: Vec<RowResult> = futures::stream::iter(rows)
+        .map(|row| {
+            process_row(
+                row,
+                sender,
+                templates,
+                password_policy,
+                send_policy,
+                assume_yes,
+            )
+        })
+        .buffer_unordered(concurrency.max(1))
+        .collect()
+        .await;
+    results.sort_by_key(|r| r.row);
+    results
+}
+
+async fn process_row(
+    row: Row,
+    sender: &SendGridSender,
+    templates: &Templates,
+    password_policy: &PasswordPolicy,
+    send_policy: &Policy,
+    assume_yes: bool,
+) -> RowResult {
+    let work_dir =
+        std::env::temp_dir().join(format!("ppap-batch-{}-{}", std::process::id(), row.number));
+    let mut digests = Vec::new();
+    let mut archive_size = 0;
+    let reference = sender::reference_code();
+    let mut receipts: Vec<Receipt> = Vec::new();
+
+    let result: anyhow::Result<()> = async {
+        for file in &row.files {
+            digests.push(FileDigest::from_path(file)?);
+        }
+        for warning in send_policy.dlp.enforce(&row.files)? {
+            println!("[row {}] [WARNING] {}", row.number, warning);
+        }
+        std::fs::create_dir_all(&work_dir)?;
+        let archive_path = work_dir.join(archive_name(&row)?);
+        let archive_path = archive_path.to_string_lossy().into_owned();
+        let password = zip::compress_files(
+            &row.files,
+            &archive_path,
+            password_policy,
+            &ArchiveFormat::default(),
+        )?;
+        archive_size = std::fs::metadata(&archive_path)?.len();
+        let infected = send_policy.malware_scan.scan(&row.files)?;
+        if !infected.is_empty() {
+            return Err(anyhow::anyhow!("Malware found: {}", infected.join("; ")));
+        }
+        policy::enforce(
+            send_policy,
+            &Envelope {
+                to: &row.recipients,
+                cc: &[],
+                password_to: &[],
+                archive_size,
+            },
+            assume_yes,
+            false,
+        )?;
+
+        let mut vars: Vec<(&str, &str)> = row
+            .vars
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+
+        println!(
+            "[row {}] Sending email #1 to {}",
+            row.number,
+            row.recipients.join(", ")
+        );
+        let parts = zip::split_archive(&archive_path, sender.max_attachment_size(None))?;
+        sender
+            .send_archive(
+                &row.recipients,
+                &[],
+                &template::subject(&templates.zip_subject, &vars, &reference),
+                &template::render(&templates.zip_body, &vars),
+                &parts,
+                &sender::reference_headers(&reference),
+                &mut receipts,
+            )
+            .await?;
+
+        println!("[row {}] Sending email #2", row.number);
+        vars.push(("password", &password));
+        let mut headers = sender::reference_headers(&reference);
+        headers.extend(sender::reply_headers(&receipts[0].message_id));
+        let receipt = sender
+            .send_email(
+                &row.recipients,
+                &[],
+                &template::subject(&templates.password_subject, &vars, &reference),
+                &template::render(&templates.password_body, &vars),
+                &headers,
+            )
+            .await?;
+        receipts.push(receipt);
+        Ok(())
+    }
+    .await;
+    let _ = std::fs::remove_dir_all(&work_dir);
+
+    let outcome = match (&result, !receipts.is_empty()) {
+        (Ok(()), _) => Outcome::Delivered,
+        (Err(_), true) => Outcome::PasswordFailed,
+        (Err(_), false) => Outcome::Failed,
+    };
+    let error = result.as_ref().err().map(|e| e.to_string());
+    match &error {
+        None => println!("[row {}] Sent successfully", row.number),
+        Some(e) => println!("[row {}] Failed: {}", row.number, e),
+    }
+
+    let record = TransferRecord {
+        timestamp: chrono::Utc::now(),
+        recipients: row.recipients.clone(),
+        cc: Vec::new(),
+        password_recipients: Vec::new(),
+        files: digests,
+        archive_size,
+        message_ids: receipts.iter().map(|r| r.message_id.clone()).collect(),
+        reference: reference.clone(),
+        receipts: receipts.clone(),
+        secure: false,
+        outcome: outcome.clone(),
+        error: error.clone(),
+    };
+    if !sender.is_sandbox() {
+        if let Err(e) = history::append(&history::history_file_path(), &record) {
+            println!("[WARNING] Failed to record transfer history: {}", e);
+        }
+    }
+
+    RowResult {
+        row: row.number,
+        recipients: row.recipients.join(";"),
+        files: row.files.join(";"),
+        status: outcome,
+        reference,
+        message_ids: record.message_ids.join(";"),
+        provider_message_ids: receipts
+            .iter()
+            .filter_map(|r| r.provider_message_id.as_deref())
+            .collect::<Vec<_>>()
+            .join(";"),
+        error: error.unwrap_or_default(),
+    }
+}
+
+/// Uses the `archive` column when present, otherwise names the archive after a single file.
+/// The name stays inside the row's temporary directory, so it cannot contain a path.
+fn archive_name(row: &Row) -> anyhow::Result<String> {
+    if let Some(name) = row.vars.get("archive").filter(|n| !n.trim().is_empty()) {
+        let name = name.trim();
+        if name.contains(['/', '\\']) || Path::new(name).file_name() != Some(name.as_ref()) {
+            return Err(anyhow::anyhow!(
+                "Invalid archive name '{}': use a file name without a directory",
+                name
+            ));
+        }
+        return Ok(if name.ends_with(".zip") {
+            name.to_string()
+        } else {
+            format!("{}.zip", name)
+        });
+    }
+    Ok(match row.files.as_slice() {
+        [single] => format!(
+            "{}.zip",
+            Path::new(single)
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or("file")
+        ),
+        _ => "files.zip".to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_read_manifest() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let manifest = temp_dir.path().join("manifest.csv");
+        std::fs::write(
+            &manifest,
+            "files,recipients,name\n\
+             statement.pdf,a@example.com,Acme\n\
+             \"x.pdf; y.pdf\",\"b@example.com;c@example.com\",Globex\n",
+        )?;
+
+        let rows = read_manifest(&manifest)?;
+        assert_eq!(rows.len(), 2);
+        assert_eq!(
+            rows[0].files,
+            vec![temp_dir.path().join("statement.pdf").to_string_lossy()]
+        );
+        assert_eq!(rows[1].files.len(), 2);
+        assert_eq!(rows[1].recipients, vec!["b@example.com", "c@example.com"]);
+        assert_eq!(rows[1].vars.get("name").map(String::as_str), Some("Globex"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_manifest_without_recipients_column() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let manifest = temp_dir.path().join("manifest.csv");
+        std::fs::write(&manifest, "files,name\nstatement.pdf,Acme\n")?;
+
+        assert!(read_manifest(&manifest).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_manifest_with_reserved_column() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let manifest = temp_dir.path().join("manifest.csv");
+        std::fs::write(
+            &manifest,
+            "files,recipients,password
+statement.pdf,a@example.com,hunter2
+",
+        )?;
+
+        let error = read_manifest(&manifest).unwrap_err();
+        assert!(error.to_string().contains("'password' is reserved"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_archive_name() -> anyhow::Result<()> {
+        let mut row = Row {
+            number: 1,
+            files: vec!["/tmp/statement.pdf".to_string()],
+            recipients: vec!["a@example.com".to_string()],
+            vars: BTreeMap::new(),
+        };
+        assert_eq!(archive_name(&row)?, "statement.pdf.zip");
+
+        row.files.push("/tmp/other.pdf".to_string());
+        assert_eq!(archive_name(&row)?, "files.zip");
+
+        row.vars
+            .insert("archive".to_string(), "acme-2026-01".to_string());
+        assert_eq!(archive_name(&row)?, "acme-2026-01.zip");
+
+        for name in [
+            "../../home/u/.bashrc",
+            "/etc/cron.d/x",
+            "sub\\dir",
+            "..",
+            ".",
+        ] {
+            row.vars.insert("archive".to_string(), name.to_string());
+            assert!(archive_name(&row).is_err(), "{} was accepted", name);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_results() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("results.csv");
+        write_results(
+            &path,
+            &[RowResult {
+                row: 1,
+                recipients: "a@example.com".to_string(),
+                files: "statement.pdf".to_string(),
+                status: Outcome::PasswordFailed,
+                reference: "K7Q2ZP".to_string(),
+                message_ids: "<a.ppap@example.com>".to_string(),
+                provider_message_ids: "14c5d75ce93.dfd.64b469".to_string(),
+                error: "Status: 400".to_string(),
+            }],
+        )?;
+
+        let content = std::fs::read_to_string(&path)?;
+        assert_eq!(
+            content,
+            "row,recipients,files,status,reference,message_ids,provider_message_ids,error\n\
+             1,a@example.com,statement.pdf,password_failed,K7Q2ZP,<a.ppap@example.com>,\
+             14c5d75ce93.dfd.64b469,Status: 400\n"
+        );
+        Ok(())
+    }
+}
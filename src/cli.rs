@@ -0,0 +1,414 @@
+use crate::output::OutputFormat;
+use crate::transfer::Delivery;
+use crate::zip::Encryption;
+use clap::{Args, Parser, Subcommand};
+
+#[derive(Parser, Debug)]
+#[command(name = "ppap")]
+#[command(author = "Rindrics")]
+#[command(version = "0.1.0")]
+#[command(about = "CLI tool that uses traditional Japanese file sharing protocol")]
+#[command(
+    arg_required_else_help = true,
+    subcommand_negates_reqs = true,
+    args_conflicts_with_subcommands = true
+)]
+pub struct Opts {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// `ppap FILE EMAIL` is kept as an alias for `ppap send FILE EMAIL`.
+    #[command(flatten)]
+    pub send: Option<SendArgs>,
+
+    #[arg(
+        short = 'p',
+        long = "profile",
+        global = true,
+        value_name = "NAME",
+        help = "Use the named profile from the config file"
+    )]
+    pub profile: Option<String>,
+}
+
+impl Opts {
+    pub fn into_command(self) -> Command {
+        match (self.command, self.send) {
+            (Some(command), _) => command,
+            (None, Some(send)) => Command::Send(send),
+            (None, None) => unreachable!("clap requires FILE and EMAIL without a subcommand"),
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    #[command(about = "Send a file as a password-protected ZIP, then send the password")]
+    Send(SendArgs),
+
+    #[command(about = "Send one archive per row of a CSV manifest (mail merge)")]
+    Batch(BatchArgs),
+
+    #[command(about = "Extract a password-protected ZIP received via ppap")]
+    Unzip(UnzipArgs),
+
+    #[command(about = "Host archives sent with --delivery serve over HTTPS")]
+    Serve(ServeArgs),
+
+    #[command(about = "Show the log of past transfers")]
+    History(HistoryArgs),
+
+    #[command(about = "Manage password emails waiting for their --after delay")]
+    Schedule {
+        #[command(subcommand)]
+        action: ScheduleAction,
+    },
+
+    #[command(about = "Send scheduled password emails left behind by a stopped ppap")]
+    Resume(ResumeArgs),
+
+    #[command(about = "Verify the tamper-evident transfer log")]
+    Audit {
+        #[command(subcommand)]
+        action: AuditAction,
+    },
+
+    #[command(about = "Inspect the configuration")]
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+}
+
+#[derive(Args, Debug)]
+pub struct SendArgs {
+    #[arg(help = "Target file to be sent")]
+    pub file: String,
+
+    #[arg(help = "Email address of the recipient, or @alias from the address book")]
+    pub email: String,
+
+    #[arg(
+        short = 's',
+        long = "secure",
+        help = "Enhance security by sending an incorrect password (Good luck explaining this to your recipient)"
+    )]
+    pub secure: bool,
+
+    #[arg(
+        short = 'a',
+        long = "after",
+        value_name = "HOURS",
+        help = "Delay password delivery by specified hours (Because waiting makes everything more secure)"
+    )]
+    pub after: Option<u64>,
+
+    #[arg(
+        long = "output",
+        value_enum,
+        default_value_t = OutputFormat::Text,
+        help = "Print a single JSON result to stdout instead of progress messages"
+    )]
+    pub output: OutputFormat,
+
+    #[arg(
+        short = 'y',
+        long = "yes",
+        help = "Confirm sending to first-time recipients when the send policy asks for it"
+    )]
+    pub yes: bool,
+
+    #[arg(
+        long = "strip-metadata",
+        help = "Remove EXIF, PDF document info and Office properties from copies of the files before archiving"
+    )]
+    pub strip_metadata: bool,
+
+    #[arg(
+        long = "delivery",
+        value_enum,
+        help = "Attach the archive, or send a download link from the storage bucket or `ppap serve` [default: attachment]"
+    )]
+    pub delivery: Option<Delivery>,
+
+    #[arg(
+        long = "encryption",
+        value_enum,
+        help = "Password-protect the ZIP (aes, zipcrypto), or encrypt it to the recipients' public keys (age, pgp) and skip the password email [default: aes]"
+    )]
+    pub encryption: Option<Encryption>,
+
+    #[arg(
+        long = "from-name",
+        value_name = "NAME",
+        help = "Display name shown with the from address"
+    )]
+    pub from_name: Option<String>,
+
+    #[arg(
+        long = "reply-to",
+        value_name = "EMAIL",
+        help = "Address that replies go to"
+    )]
+    pub reply_to: Option<String>,
+
+    #[arg(
+        long = "header",
+        value_name = "NAME: VALUE",
+        value_parser = parse_header,
+        help = "Add a header field to both emails, e.g. \"X-Classification: Confidential\" (repeatable)"
+    )]
+    pub headers: Vec<(String, String)>,
+
+    #[arg(
+        long = "sandbox",
+        help = "Have SendGrid validate the emails without delivering them (REST API only)"
+    )]
+    pub sandbox: bool,
+}
+
+/// `NAME: VALUE` of `--header`.
+fn parse_header(value: &str) -> Result<(String, String), String> {
+    let (name, value) = value
+        .split_once(':')
+        .ok_or_else(|| "expected NAME: VALUE".to_string())?;
+    Ok((name.trim().to_string(), value.trim().to_string()))
+}
+
+#[derive(Args, Debug)]
+pub struct BatchArgs {
+    #[arg(
+        help = "CSV with `files` and `recipients` columns; other columns are template variables"
+    )]
+    pub manifest: String,
+
+    #[arg(
+        short = 'j',
+        long = "concurrency",
+        value_name = "N",
+        default_value_t = 4,
+        help = "Number of rows sent in parallel"
+    )]
+    pub concurrency: usize,
+
+    #[arg(
+        short = 'o',
+        long = "results",
+        value_name = "PATH",
+        help = "Where to write the result CSV [default: <manifest>.results.csv]"
+    )]
+    pub results: Option<String>,
+
+    #[arg(
+        short = 'y',
+        long = "yes",
+        help = "Confirm sending to first-time recipients when the send policy asks for it"
+    )]
+    pub yes: bool,
+
+    #[arg(
+        long = "from-name",
+        value_name = "NAME",
+        help = "Display name shown with the from address"
+    )]
+    pub from_name: Option<String>,
+
+    #[arg(
+        long = "reply-to",
+        value_name = "EMAIL",
+        help = "Address that replies go to"
+    )]
+    pub reply_to: Option<String>,
+
+    #[arg(
+        long = "header",
+        value_name = "NAME: VALUE",
+        value_parser = parse_header,
+        help = "Add a header field to both emails, e.g. \"X-Classification: Confidential\" (repeatable)"
+    )]
+    pub headers: Vec<(String, String)>,
+
+    #[arg(
+        long = "sandbox",
+        help = "Have SendGrid validate the emails without delivering them (REST API only)"
+    )]
+    pub sandbox: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct UnzipArgs {
+    #[arg(help = "ZIP file to be extracted")]
+    pub archive: String,
+
+    #[arg(
+        short = 'P',
+        long = "password",
+        help = "Password from the second email (prompted if omitted)"
+    )]
+    pub password: Option<String>,
+
+    #[arg(
+        short = 'o',
+        long = "output",
+        value_name = "DIR",
+        default_value = ".",
+        help = "Directory to extract into"
+    )]
+    pub output: String,
+}
+
+#[derive(Args, Debug)]
+pub struct ServeArgs {
+    #[arg(
+        long = "listen",
+        value_name = "ADDR",
+        help = "Address to listen on [default: the profile's serve.listen or 0.0.0.0:8443]"
+    )]
+    pub listen: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct HistoryArgs {
+    #[arg(
+        short = 'r',
+        long = "recipient",
+        help = "Only show transfers to recipients containing this text"
+    )]
+    pub recipient: Option<String>,
+
+    #[arg(
+        long = "since",
+        value_name = "YYYY-MM-DD",
+        help = "Only show transfers on or after this date (UTC)"
+    )]
+    pub since: Option<chrono::NaiveDate>,
+
+    #[arg(
+        long = "until",
+        value_name = "YYYY-MM-DD",
+        help = "Only show transfers on or before this date (UTC)"
+    )]
+    pub until: Option<chrono::NaiveDate>,
+}
+
+#[derive(Args, Debug)]
+pub struct ResumeArgs {
+    #[arg(help = "Reference code of the transfer [default: all scheduled password emails]")]
+    pub id: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ScheduleAction {
+    #[command(about = "List password emails that have not been sent yet")]
+    List,
+
+    #[command(about = "Cancel a scheduled password email")]
+    Cancel {
+        #[arg(help = "Reference code of the transfer")]
+        id: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum AuditAction {
+    #[command(about = "Detect modified, deleted or reordered transfer records")]
+    Verify,
+
+    #[command(about = "Generate the local key used to HMAC new transfer records")]
+    InitKey,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigAction {
+    #[command(about = "Print the effective configuration with secrets masked")]
+    Show,
+
+    #[command(about = "Store the SendGrid API key in the OS keyring")]
+    SetKey {
+        #[arg(long = "stdin", help = "Read the API key from standard input")]
+        stdin: bool,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Command {
+        Opts::try_parse_from(args).unwrap().into_command()
+    }
+
+    #[test]
+    fn test_positional_form_is_alias_for_send() {
+        let Command::Send(args) = parse(&["ppap", "-s", "doc.pdf", "a@example.com"]) else {
+            panic!("expected send");
+        };
+        assert_eq!(args.file, "doc.pdf");
+        assert_eq!(args.email, "a@example.com");
+        assert!(args.secure);
+    }
+
+    #[test]
+    fn test_send_subcommand() {
+        let Command::Send(args) = parse(&["ppap", "send", "--after", "2", "doc.pdf", "a@b.jp"])
+        else {
+            panic!("expected send");
+        };
+        assert_eq!(args.after, Some(2));
+    }
+
+    #[test]
+    fn test_sender_options() {
+        let Command::Send(args) = parse(&[
+            "ppap",
+            "send",
+            "--reply-to",
+            "support@example.com",
+            "--header",
+            "X-Classification: Confidential",
+            "doc.pdf",
+            "a@b.jp",
+        ]) else {
+            panic!("expected send");
+        };
+        assert_eq!(args.reply_to.as_deref(), Some("support@example.com"));
+        assert_eq!(
+            args.headers,
+            vec![("X-Classification".to_string(), "Confidential".to_string())]
+        );
+        assert!(
+            Opts::try_parse_from(["ppap", "send", "--header", "X", "doc.pdf", "a@b.jp"]).is_err()
+        );
+    }
+
+    #[test]
+    fn test_config_subcommand_with_global_profile() {
+        let opts = Opts::try_parse_from(["ppap", "config", "show", "--profile", "work"]).unwrap();
+        assert_eq!(opts.profile.as_deref(), Some("work"));
+        assert!(matches!(
+            opts.into_command(),
+            Command::Config {
+                action: ConfigAction::Show
+            }
+        ));
+    }
+
+    #[test]
+    fn test_schedule_and_resume_subcommands() {
+        assert!(matches!(
+            parse(&["ppap", "schedule", "cancel", "K7Q2ZP"]),
+            Command::Schedule {
+                action: ScheduleAction::Cancel { id }
+            } if id == "K7Q2ZP"
+        ));
+        assert!(matches!(
+            parse(&["ppap", "resume"]),
+            Command::Resume(ResumeArgs { id: None })
+        ));
+    }
+
+    #[test]
+    fn test_missing_email_is_rejected() {
+        assert!(Opts::try_parse_from(["ppap", "doc.pdf"]).is_err());
+    }
+}
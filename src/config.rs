@@ -0,0 +1,302 @@
+use crate::email::dkim::DkimConfig;
+use crate::email::sendgrid::SendGridOptions;
+use crate::email::smime::SmimeConfig;
+use crate::email::smtp::SmtpConfig;
+use crate::serve::ServeConfig;
+use crate::storage::StorageConfig;
+use crate::template::Templates;
+use crate::transfer::Delivery;
+use crate::zip::{PasswordPolicy, MIN_PASSWORD_LENGTH};
+use anyhow::Context;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    pub default_profile: Option<String>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+    /// Named template sets that can be picked per transfer.
+    #[serde(default)]
+    pub templates: BTreeMap<String, Templates>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub provider: Option<String>,
+    pub from_address: Option<String>,
+    /// Display name shown with the from address.
+    pub from_name: Option<String>,
+    pub reply_to: Option<String>,
+    /// Extra header fields added to every email, such as `X-Classification`.
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    pub delay_hours: Option<u64>,
+    pub password_length: Option<usize>,
+    pub password_symbols: Option<bool>,
+    /// Remove EXIF, PDF document info and Office properties before archiving.
+    pub strip_metadata: Option<bool>,
+    /// Size limit of the outgoing gateway, when lower than the provider's.
+    pub max_message_mb: Option<u64>,
+    pub delivery: Option<Delivery>,
+    /// Bucket used by `delivery = "link"`.
+    pub storage: Option<StorageConfig>,
+    /// Download server used by `delivery = "serve"`.
+    pub serve: Option<ServeConfig>,
+    /// A relay of your own instead of SendGrid's.
+    pub smtp: Option<SmtpConfig>,
+    /// Sign (and encrypt) outgoing email; needs `provider = "smtp"`.
+    pub smime: Option<SmimeConfig>,
+    /// DKIM-sign outgoing email; needs `provider = "smtp"`.
+    pub dkim: Option<DkimConfig>,
+    /// Categories, custom args and tracking of REST API mail.
+    pub sendgrid: Option<SendGridOptions>,
+    /// Let SendGrid validate the emails without delivering them, like `--sandbox`.
+    pub sandbox: Option<bool>,
+    pub templates: Option<Templates>,
+}
+
+impl Profile {
+    pub fn password_policy(&self) -> PasswordPolicy {
+        let default = PasswordPolicy::default();
+        PasswordPolicy {
+            length: self.password_length.unwrap_or(default.length),
+            symbols: self.password_symbols.unwrap_or(default.symbols),
+        }
+    }
+
+    pub fn templates(&self) -> Templates {
+        self.templates.clone().unwrap_or_default()
+    }
+}
+
+/// The profile selected for this run, together with where it came from.
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub config_path: PathBuf,
+    pub config_found: bool,
+    pub profile_name: Option<String>,
+    pub profile: Profile,
+    pub templates: BTreeMap<String, Templates>,
+}
+
+impl Settings {
+    fn from_values(
+        config_path: PathBuf,
+        file: Option<ConfigFile>,
+        cli_profile: Option<String>,
+        env_profile: Option<String>,
+    ) -> anyhow::Result<Self> {
+        let config_found = file.is_some();
+        let file = file.unwrap_or_default();
+
+        let requested = cli_profile.or(env_profile);
+        let explicit = requested.is_some();
+        let profile_name = requested
+            .or_else(|| file.default_profile.clone())
+            .or_else(|| {
+                file.profiles
+                    .contains_key("default")
+                    .then(|| "default".to_string())
+            });
+
+        let profile = match &profile_name {
+            Some(name) => match file.profiles.get(name) {
+                Some(profile) => profile.clone(),
+                None if explicit || file.default_profile.is_some() => {
+                    return Err(anyhow::anyhow!(
+                        "Profile '{}' is not defined in {}",
+                        name,
+                        config_path.display()
+                    ));
+                }
+                None => Profile::default(),
+            },
+            None => Profile::default(),
+        };
+
+        if let Some(length) = profile
+            .password_length
+            .filter(|&length| length < MIN_PASSWORD_LENGTH)
+        {
+            return Err(anyhow::anyhow!(
+                "password_length = {} in {} is too short, use at least {}",
+                length,
+                config_path.display(),
+                MIN_PASSWORD_LENGTH
+            ));
+        }
+
+        Ok(Self {
+            config_path,
+            config_found,
+            profile_name,
+            profile,
+            templates: file.templates,
+        })
+    }
+
+    pub fn load(cli_profile: Option<String>) -> anyhow::Result<Self> {
+        let config_path = config_file_path();
+        let file = read_config_file(&config_path)?;
+        let env_profile = std::env::var("PPAP_PROFILE").ok();
+
+        Self::from_values(config_path, file, cli_profile, env_profile)
+    }
+}
+
+/// `$XDG_CONFIG_HOME/ppap`, falling back to `~/.config/ppap`.
+pub fn config_dir() -> PathBuf {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| dirs::home_dir().map(|home| home.join(".config")))
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("ppap")
+}
+
+/// `$XDG_DATA_HOME/ppap`, falling back to `~/.local/share/ppap`.
+pub fn data_dir() -> PathBuf {
+    std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| dirs::home_dir().map(|home| home.join(".local").join("share")))
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("ppap")
+}
+
+pub fn config_file_path() -> PathBuf {
+    std::env::var_os("PPAP_CONFIG")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| config_dir().join("config.toml"))
+}
+
+fn read_config_file(path: &Path) -> anyhow::Result<Option<ConfigFile>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read config file: {}", path.display()))?;
+    let file = toml::from_str(&content)
+        .with_context(|| format!("Failed to parse config file: {}", path.display()))?;
+    Ok(Some(file))
+}
+
+pub fn mask_secret(secret: &str) -> String {
+    let visible: String = secret.chars().take(4).collect();
+    if secret.chars().count() <= 8 {
+        "********".to_string()
+    } else {
+        format!("{}********", visible)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = r#"
+default_profile = "work"
+
+[profiles.work]
+provider = "smtp"
+from_address = "work@example.com"
+reply_to = "support@example.com"
+headers = { X-Classification = "Confidential" }
+delay_hours = 2
+password_length = 24
+delivery = "link"
+
+[profiles.work.storage]
+endpoint = "http://localhost:9000"
+bucket = "ppap"
+
+[profiles.personal]
+from_address = "me@example.com"
+smime = { pkcs12 = "me.p12", encrypt = true }
+
+[profiles.personal.templates]
+zip_subject = "Here comes the ZIP"
+
+[templates.english]
+password_subject = "Your password"
+"#;
+
+    fn sample() -> ConfigFile {
+        toml::from_str(SAMPLE).unwrap()
+    }
+
+    #[test]
+    fn test_default_profile_is_selected() {
+        let settings =
+            Settings::from_values(PathBuf::from("config.toml"), Some(sample()), None, None)
+                .unwrap();
+        assert_eq!(settings.profile_name.as_deref(), Some("work"));
+        assert_eq!(settings.profile.delay_hours, Some(2));
+        assert_eq!(settings.profile.headers["X-Classification"], "Confidential");
+        assert_eq!(settings.profile.password_policy().length, 24);
+        assert_eq!(settings.profile.delivery, Some(Delivery::Link));
+        assert_eq!(settings.profile.storage.unwrap().region, "us-east-1");
+        assert_eq!(
+            settings.templates["english"].password_subject,
+            "Your password"
+        );
+    }
+
+    #[test]
+    fn test_cli_profile_overrides_env_profile() {
+        let settings = Settings::from_values(
+            PathBuf::from("config.toml"),
+            Some(sample()),
+            Some("personal".to_string()),
+            Some("work".to_string()),
+        )
+        .unwrap();
+        assert_eq!(settings.profile_name.as_deref(), Some("personal"));
+        assert!(settings.profile.smime.as_ref().unwrap().encrypt);
+        let templates = settings.profile.templates();
+        assert_eq!(templates.zip_subject, "Here comes the ZIP");
+        assert_eq!(
+            templates.password_subject,
+            Templates::default().password_subject
+        );
+    }
+
+    #[test]
+    fn test_unknown_profile_is_rejected() {
+        let result = Settings::from_values(
+            PathBuf::from("config.toml"),
+            Some(sample()),
+            Some("missing".to_string()),
+            None,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_short_password_length_is_rejected() {
+        let mut file = sample();
+        file.profiles.get_mut("work").unwrap().password_length = Some(0);
+        let error = Settings::from_values(PathBuf::from("config.toml"), Some(file), None, None)
+            .unwrap_err();
+        assert!(error.to_string().contains("password_length = 0"));
+    }
+
+    #[test]
+    fn test_missing_config_file_uses_defaults() {
+        let settings =
+            Settings::from_values(PathBuf::from("config.toml"), None, None, None).unwrap();
+        assert!(!settings.config_found);
+        assert!(settings.profile_name.is_none());
+        assert_eq!(settings.profile.password_policy().length, 16);
+    }
+
+    #[test]
+    fn test_mask_secret() {
+        assert_eq!(mask_secret("SG.abcdefghijkl"), "SG.a********");
+        assert_eq!(mask_secret("short"), "********");
+    }
+}
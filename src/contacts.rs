@@ -0,0 +1,217 @@
+use crate::address;
+use crate::config::Settings;
+use crate::pubkey::PublicKeys;
+use crate::transfer::Transfer;
+use crate::zip::{Encryption, FilenameEncoding};
+use anyhow::Context;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// An address book entry, used as `@alias` wherever a recipient is expected.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Contact {
+    pub to: Vec<String>,
+    #[serde(default)]
+    pub cc: Vec<String>,
+    /// Receives the password email instead of `to` and `cc`.
+    #[serde(default)]
+    pub password_to: Vec<String>,
+    /// Name of a `[templates.NAME]` set in the config file, e.g. one per language.
+    pub template: Option<String>,
+    pub encryption: Option<Encryption>,
+    pub filename_encoding: Option<FilenameEncoding>,
+    /// Size limit of the contact's mail gateway; larger archives are split.
+    pub max_message_mb: Option<u64>,
+    /// Public keys for `encryption = "age"` or `"pgp"` and S/MIME certificates, used for every
+    /// address of the contact.
+    #[serde(default)]
+    pub keys: PublicKeys,
+}
+
+pub type AddressBook = BTreeMap<String, Contact>;
+
+impl Contact {
+    /// Adds the contact's addresses to the transfer and applies its preferences.
+    pub fn apply(&self, transfer: &mut Transfer, settings: &Settings) -> anyhow::Result<()> {
+        transfer.recipients.extend(self.to.iter().cloned());
+        transfer.cc.extend(self.cc.iter().cloned());
+        transfer
+            .password_recipients
+            .extend(self.password_to.iter().cloned());
+        if let Some(name) = &self.template {
+            transfer.templates = settings.templates.get(name).cloned().ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Template '{}' is not defined in {}",
+                    name,
+                    settings.config_path.display()
+                )
+            })?;
+        }
+        if let Some(encryption) = self.encryption {
+            transfer.format.encryption = encryption;
+        }
+        if let Some(encoding) = self.filename_encoding {
+            transfer.format.filename_encoding = encoding;
+        }
+        if let Some(mb) = self.max_message_mb {
+            transfer.max_message_mb = Some(transfer.max_message_mb.map_or(mb, |m| m.min(mb)));
+        }
+        if !self.keys.is_empty() {
+            for recipient in self.to.iter().chain(&self.cc) {
+                transfer.public_keys.insert(
+                    address::email_of(recipient).to_lowercase(),
+                    self.keys.clone(),
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `@acme-legal` refers to the contact `acme-legal`.
+pub fn alias(recipient: &str) -> Option<&str> {
+    recipient.strip_prefix('@')
+}
+
+pub fn contacts_file_path() -> PathBuf {
+    std::env::var_os("PPAP_CONTACTS")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| crate::config::config_dir().join("contacts.toml"))
+}
+
+/// Reads the address book. A missing file is an empty address book.
+pub fn load(path: &Path) -> anyhow::Result<AddressBook> {
+    if !path.exists() {
+        return Ok(AddressBook::new());
+    }
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read contacts: {}", path.display()))?;
+    let book: AddressBook = toml::from_str(&content)
+        .with_context(|| format!("Failed to parse contacts: {}", path.display()))?;
+    if let Some((name, _)) = book.iter().find(|(_, contact)| contact.to.is_empty()) {
+        return Err(anyhow::anyhow!(
+            "Contact '{}' has no 'to' addresses: {}",
+            name,
+            path.display()
+        ));
+    }
+    Ok(book)
+}
+
+pub fn lookup<'a>(book: &'a AddressBook, alias: &str) -> anyhow::Result<&'a Contact> {
+    book.get(alias).ok_or_else(|| {
+        anyhow::anyhow!(
+            "Contact '@{}' is not defined in {}",
+            alias,
+            contacts_file_path().display()
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::template::Templates;
+    use crate::zip::ArchiveFormat;
+    use tempfile::tempdir;
+
+    const CONTACTS: &str = r#"
+[acme-legal]
+to = ["legal@acme.example", "counsel@acme.example"]
+cc = ["sales@ours.example"]
+password_to = ["legal-pw@acme.example"]
+template = "english"
+encryption = "zipcrypto"
+filename_encoding = "shift_jis"
+max_message_mb = 10
+
+[bob]
+to = ["bob@example.com"]
+encryption = "age"
+keys = { age = ["age1bob"] }
+"#;
+
+    fn settings() -> Settings {
+        let mut templates = BTreeMap::new();
+        templates.insert(
+            "english".to_string(),
+            Templates {
+                password_subject: "Your password".to_string(),
+                ..Default::default()
+            },
+        );
+        Settings {
+            config_path: PathBuf::from("config.toml"),
+            config_found: true,
+            profile_name: None,
+            profile: Default::default(),
+            templates,
+        }
+    }
+
+    fn transfer() -> Transfer {
+        Transfer {
+            files: vec!["report.pdf".to_string()],
+            recipients: vec![],
+            cc: vec![],
+            password_recipients: vec![],
+            secure: false,
+            after: None,
+            templates: Templates::default(),
+            format: ArchiveFormat::default(),
+            assume_yes: false,
+            strip_metadata: false,
+            max_message_mb: None,
+            public_keys: Default::default(),
+            delivery: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_apply_contact() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("contacts.toml");
+        std::fs::write(&path, CONTACTS)?;
+        let book = load(&path)?;
+
+        let mut transfer = transfer();
+        lookup(&book, "acme-legal")?.apply(&mut transfer, &settings())?;
+        assert_eq!(transfer.recipients.len(), 2);
+        assert_eq!(transfer.cc, vec!["sales@ours.example"]);
+        assert_eq!(transfer.password_recipients, vec!["legal-pw@acme.example"]);
+        assert_eq!(transfer.templates.password_subject, "Your password");
+        assert_eq!(transfer.format.encryption, Encryption::ZipCrypto);
+        assert_eq!(
+            transfer.format.filename_encoding,
+            FilenameEncoding::ShiftJis
+        );
+        assert_eq!(transfer.max_message_mb, Some(10));
+
+        let mut transfer = self::transfer();
+        lookup(&book, "bob")?.apply(&mut transfer, &settings())?;
+        assert_eq!(transfer.recipients, vec!["bob@example.com"]);
+        assert_eq!(transfer.format.encryption, Encryption::Age);
+        assert_eq!(transfer.public_keys["bob@example.com"].age, vec!["age1bob"]);
+
+        assert!(lookup(&book, "nobody").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_template_is_rejected() {
+        let contact = Contact {
+            to: vec!["a@example.com".to_string()],
+            template: Some("klingon".to_string()),
+            ..Default::default()
+        };
+        assert!(contact.apply(&mut transfer(), &settings()).is_err());
+    }
+
+    #[test]
+    fn test_alias() {
+        assert_eq!(alias("@acme-legal"), Some("acme-legal"));
+        assert_eq!(alias("legal@acme.example"), None);
+    }
+}
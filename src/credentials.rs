@@ -0,0 +1,275 @@
+use crate::output::say;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use anyhow::Context;
+use base64::Engine;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+const SERVICE: &str = "ppap";
+const PASSPHRASE_ENV: &str = "PPAP_CREDENTIALS_PASSPHRASE";
+
+#[derive(Debug)]
+pub enum Backend {
+    Keyring,
+    EncryptedFile(PathBuf),
+}
+
+impl std::fmt::Display for Backend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Backend::Keyring => write!(f, "OS keyring"),
+            Backend::EncryptedFile(path) => write!(f, "encrypted file {}", path.display()),
+        }
+    }
+}
+
+/// Account name under which the API key of a profile is stored.
+pub fn account_name(profile_name: Option<&str>) -> String {
+    format!("sendgrid-api-key:{}", profile_name.unwrap_or("default"))
+}
+
+/// Accounts to look the API key up under: the profile's own, then the default one.
+pub fn account_names(profile_name: Option<&str>) -> Vec<String> {
+    let mut accounts = vec![account_name(profile_name)];
+    if profile_name.is_some_and(|name| name != "default") {
+        accounts.push(account_name(None));
+    }
+    accounts
+}
+
+pub fn credentials_file_path() -> PathBuf {
+    crate::config::config_dir().join("credentials.enc")
+}
+
+/// Stores the secret in the OS keyring, falling back to the encrypted file
+/// when no keyring service is reachable (e.g. headless Linux without D-Bus).
+pub fn store_secret(
+    account: &str,
+    secret: &str,
+    passphrase: impl FnOnce() -> anyhow::Result<String>,
+) -> anyhow::Result<Backend> {
+    match keyring::Entry::new(SERVICE, account).and_then(|entry| entry.set_password(secret)) {
+        Ok(()) => Ok(Backend::Keyring),
+        Err(e) => {
+            say!("OS keyring is not available ({}), using encrypted file", e);
+            let store = EncryptedFileStore::new(credentials_file_path());
+            store.set(account, secret, &passphrase()?)?;
+            Ok(Backend::EncryptedFile(store.path))
+        }
+    }
+}
+
+/// Looks the secret up in the OS keyring first, then in the encrypted file.
+/// The encrypted file is only consulted when `PPAP_CREDENTIALS_PASSPHRASE` is set.
+pub fn load_secret(account: &str) -> anyhow::Result<Option<(String, Backend)>> {
+    match keyring::Entry::new(SERVICE, account).and_then(|entry| entry.get_password()) {
+        Ok(secret) => return Ok(Some((secret, Backend::Keyring))),
+        Err(keyring::Error::NoEntry) => {}
+        Err(e) => say!("OS keyring is not available ({})", e),
+    }
+
+    let store = EncryptedFileStore::new(credentials_file_path());
+    if !store.path.exists() {
+        return Ok(None);
+    }
+    let Ok(passphrase) = std::env::var(PASSPHRASE_ENV) else {
+        say!(
+            "Found {} but {} is not set",
+            store.path.display(),
+            PASSPHRASE_ENV
+        );
+        return Ok(None);
+    };
+    Ok(store
+        .get(account, &passphrase)?
+        .map(|secret| (secret, Backend::EncryptedFile(store.path))))
+}
+
+/// The API key of the profile, or the default account's key when the profile has none.
+pub fn load_api_key(profile_name: Option<&str>) -> anyhow::Result<Option<(String, Backend)>> {
+    for account in account_names(profile_name) {
+        if let Some(found) = load_secret(&account)? {
+            return Ok(Some(found));
+        }
+    }
+    Ok(None)
+}
+
+pub fn passphrase_from_env() -> Option<String> {
+    std::env::var(PASSPHRASE_ENV).ok()
+}
+
+#[derive(Serialize, Deserialize)]
+struct EncryptedFile {
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+/// AES-256-GCM encrypted JSON map of account name to secret, keyed by Argon2id(passphrase).
+pub struct EncryptedFileStore {
+    path: PathBuf,
+}
+
+impl EncryptedFileStore {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    pub fn get(&self, account: &str, passphrase: &str) -> anyhow::Result<Option<String>> {
+        Ok(self.read(passphrase)?.remove(account))
+    }
+
+    pub fn set(&self, account: &str, secret: &str, passphrase: &str) -> anyhow::Result<()> {
+        let mut entries = if self.path.exists() {
+            self.read(passphrase)?
+        } else {
+            BTreeMap::new()
+        };
+        entries.insert(account.to_string(), secret.to_string());
+        self.write(&entries, passphrase)
+    }
+
+    fn read(&self, passphrase: &str) -> anyhow::Result<BTreeMap<String, String>> {
+        let content = std::fs::read_to_string(&self.path)
+            .with_context(|| format!("Failed to read credentials: {}", self.path.display()))?;
+        let file: EncryptedFile = serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse credentials: {}", self.path.display()))?;
+
+        let engine = base64::engine::general_purpose::STANDARD;
+        let salt = engine.decode(&file.salt)?;
+        let nonce = engine.decode(&file.nonce)?;
+        let ciphertext = engine.decode(&file.ciphertext)?;
+
+        let cipher = Aes256Gcm::new(&derive_key(passphrase, &salt)?);
+        let plaintext = cipher
+            .decrypt(Nonce::from_slice(&nonce), ciphertext.as_ref())
+            .map_err(|_| anyhow::anyhow!("Failed to decrypt credentials: wrong passphrase?"))?;
+        Ok(serde_json::from_slice(&plaintext)?)
+    }
+
+    fn write(&self, entries: &BTreeMap<String, String>, passphrase: &str) -> anyhow::Result<()> {
+        let mut salt = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut salt);
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+
+        let cipher = Aes256Gcm::new(&derive_key(passphrase, &salt)?);
+        let ciphertext = cipher
+            .encrypt(&nonce, serde_json::to_vec(entries)?.as_ref())
+            .map_err(|_| anyhow::anyhow!("Failed to encrypt credentials"))?;
+
+        let engine = base64::engine::general_purpose::STANDARD;
+        let file = EncryptedFile {
+            salt: engine.encode(salt),
+            nonce: engine.encode(nonce),
+            ciphertext: engine.encode(ciphertext),
+        };
+
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        write_private(&self.path, serde_json::to_string_pretty(&file)?.as_bytes())
+            .with_context(|| format!("Failed to write credentials: {}", self.path.display()))
+    }
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> anyhow::Result<Key<Aes256Gcm>> {
+    let mut key = [0u8; 32];
+    argon2::Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| anyhow::anyhow!("Failed to derive key: {}", e))?;
+    Ok(key.into())
+}
+
+/// The mode only applies to new files, so an existing file is tightened before writing.
+#[cfg(unix)]
+pub fn write_private(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    file.write_all(content)
+}
+
+#[cfg(not(unix))]
+pub fn write_private(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    std::fs::write(path, content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_encrypted_file_roundtrip() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let store = EncryptedFileStore::new(temp_dir.path().join("credentials.enc"));
+
+        store.set("work", "SG.work-key", "passphrase")?;
+        store.set("personal", "SG.personal-key", "passphrase")?;
+
+        assert_eq!(
+            store.get("work", "passphrase")?.as_deref(),
+            Some("SG.work-key")
+        );
+        assert_eq!(
+            store.get("personal", "passphrase")?.as_deref(),
+            Some("SG.personal-key")
+        );
+        assert_eq!(store.get("missing", "passphrase")?, None);
+
+        let content = std::fs::read_to_string(temp_dir.path().join("credentials.enc"))?;
+        assert!(!content.contains("SG.work-key"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_encrypted_file_wrong_passphrase() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let store = EncryptedFileStore::new(temp_dir.path().join("credentials.enc"));
+
+        store.set("default", "SG.key", "correct")?;
+        assert!(store.get("default", "wrong").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_account_name() {
+        assert_eq!(account_name(None), "sendgrid-api-key:default");
+        assert_eq!(account_name(Some("work")), "sendgrid-api-key:work");
+    }
+
+    #[test]
+    fn test_profile_falls_back_to_default_account() {
+        assert_eq!(account_names(None), vec!["sendgrid-api-key:default"]);
+        assert_eq!(
+            account_names(Some("work")),
+            vec!["sendgrid-api-key:work", "sendgrid-api-key:default"]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_private_tightens_existing_file() -> anyhow::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("credentials.enc");
+        std::fs::write(&path, "old")?;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644))?;
+
+        write_private(&path, b"new")?;
+        let mode = std::fs::metadata(&path)?.permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(std::fs::read_to_string(&path)?, "new");
+        Ok(())
+    }
+}
@@ -0,0 +1,429 @@
+use serde::Deserialize;
+use std::io::Read;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    #[default]
+    Off,
+    Warn,
+    Block,
+}
+
+/// What to do when a kind of sensitive data is found, configured as `[dlp]` in the policy.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DlpPolicy {
+    #[serde(default)]
+    pub card_numbers: Action,
+    #[serde(default)]
+    pub my_number: Action,
+    #[serde(default)]
+    pub emails: Action,
+    #[serde(default)]
+    pub phone_numbers: Action,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    CardNumber,
+    MyNumber,
+    Email,
+    PhoneNumber,
+}
+
+impl std::fmt::Display for Kind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Kind::CardNumber => write!(f, "card number"),
+            Kind::MyNumber => write!(f, "My Number"),
+            Kind::Email => write!(f, "email address"),
+            Kind::PhoneNumber => write!(f, "phone number"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub kind: Kind,
+    /// The match with all but the last four characters masked.
+    pub masked: String,
+}
+
+impl DlpPolicy {
+    fn action(&self, kind: Kind) -> Action {
+        match kind {
+            Kind::CardNumber => self.card_numbers,
+            Kind::MyNumber => self.my_number,
+            Kind::Email => self.emails,
+            Kind::PhoneNumber => self.phone_numbers,
+        }
+    }
+
+    fn is_off(&self) -> bool {
+        [
+            Kind::CardNumber,
+            Kind::MyNumber,
+            Kind::Email,
+            Kind::PhoneNumber,
+        ]
+        .iter()
+        .all(|&kind| self.action(kind) == Action::Off)
+    }
+
+    /// Scans the files. Fails when a blocking finding is made, otherwise returns the warnings.
+    pub fn enforce(&self, files: &[String]) -> anyhow::Result<Vec<String>> {
+        if self.is_off() {
+            return Ok(Vec::new());
+        }
+        let mut blocked = Vec::new();
+        let mut warnings = Vec::new();
+        for file in files {
+            let Some(text) = extract_text(Path::new(file))? else {
+                continue;
+            };
+            for finding in scan(&text) {
+                let message = format!("{}: {} {}", file, finding.kind, finding.masked);
+                match self.action(finding.kind) {
+                    Action::Off => {}
+                    Action::Warn => warnings.push(message),
+                    Action::Block => blocked.push(message),
+                }
+            }
+        }
+        if !blocked.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Sensitive data found:\n  {}",
+                blocked.join("\n  ")
+            ));
+        }
+        Ok(warnings)
+    }
+}
+
+/// Text of a text-like file, or of the document parts of a docx/xlsx. `None` for other files.
+pub fn extract_text(path: &Path) -> anyhow::Result<Option<String>> {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+    if extension == "docx" || extension == "xlsx" {
+        return Ok(Some(office_text(path)?));
+    }
+
+    let content = std::fs::read(path)?;
+    if content.iter().take(8192).any(|&b| b == 0) {
+        return Ok(None);
+    }
+    if let Ok(text) = String::from_utf8(content.clone()) {
+        return Ok(Some(text));
+    }
+    // Older Japanese text files are often Shift_JIS.
+    let (text, _, had_errors) = encoding_rs::SHIFT_JIS.decode(&content);
+    Ok((!had_errors).then(|| text.into_owned()))
+}
+
+fn office_text(path: &Path) -> anyhow::Result<String> {
+    let mut archive = zip::ZipArchive::new(std::fs::File::open(path)?)?;
+    let parts: Vec<String> = archive
+        .file_names()
+        .filter(|name| {
+            name.starts_with("word/") && name.ends_with(".xml")
+                || *name == "xl/sharedStrings.xml"
+                || name.starts_with("xl/worksheets/") && name.ends_with(".xml")
+        })
+        .map(str::to_string)
+        .collect();
+
+    let mut text = String::new();
+    for part in parts {
+        let mut xml = String::new();
+        archive.by_name(&part)?.read_to_string(&mut xml)?;
+        text.push_str(&xml_text(&xml));
+        text.push('\n');
+    }
+    Ok(text)
+}
+
+/// Drops the markup. Paragraphs, rows and cells become line breaks so that values in
+/// neighbouring cells do not run together, while runs inside a paragraph are joined.
+fn xml_text(xml: &str) -> String {
+    let mut text = String::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find('<') {
+        text.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find('>') else {
+            break;
+        };
+        let tag = &rest[start + 1..start + end];
+        if [
+            "/w:p", "/w:tc", "/w:tr", "w:br", "w:tab", "/si", "/c", "/row",
+        ]
+        .iter()
+        .any(|t| tag.trim_end_matches('/').trim() == *t)
+        {
+            text.push('\n');
+        }
+        rest = &rest[start + end + 1..];
+    }
+    text.push_str(rest);
+    unescape(&text)
+}
+
+fn unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(end) = rest.find(';').filter(|&end| end <= 10) else {
+            out.push('&');
+            rest = &rest[1..];
+            continue;
+        };
+        let entity = &rest[1..end];
+        let decoded = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity
+                .strip_prefix("#x")
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                .or_else(|| entity.strip_prefix('#').and_then(|dec| dec.parse().ok()))
+                .and_then(char::from_u32),
+        };
+        match decoded {
+            Some(c) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+pub fn scan(text: &str) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    for (raw, digits, international) in number_runs(text) {
+        let kind = if (13..=19).contains(&digits.len()) && luhn_valid(&digits) {
+            Kind::CardNumber
+        } else if digits.len() == 12 && my_number_valid(&digits) {
+            Kind::MyNumber
+        } else if is_phone_number(&digits, international) {
+            Kind::PhoneNumber
+        } else {
+            continue;
+        };
+        findings.push(Finding {
+            kind,
+            masked: mask(&raw),
+        });
+    }
+    for email in emails(text) {
+        findings.push(Finding {
+            kind: Kind::Email,
+            masked: mask(&email),
+        });
+    }
+    findings
+}
+
+/// Digit sequences that may contain single spaces or hyphens between digits, as
+/// `(text, digits, starts with +)`. Full-width digits are accepted too.
+fn number_runs(text: &str) -> Vec<(String, String, bool)> {
+    let chars: Vec<char> = text.chars().collect();
+    let digit = |c: char| c.to_digit(10).or_else(|| full_width_digit(c));
+    let mut runs = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if digit(chars[i]).is_none() || (i > 0 && chars[i - 1].is_ascii_alphanumeric()) {
+            i += 1;
+            continue;
+        }
+        let international = i > 0 && chars[i - 1] == '+';
+        let start = i;
+        let mut digits = String::new();
+        while i < chars.len() {
+            if let Some(d) = digit(chars[i]) {
+                digits.push(char::from_digit(d, 10).unwrap_or('0'));
+                i += 1;
+            } else if matches!(chars[i], ' ' | '-' | 'ー' | '－')
+                && chars.get(i + 1).is_some_and(|&c| digit(c).is_some())
+            {
+                i += 1;
+            } else {
+                break;
+            }
+        }
+        if !chars.get(i).is_some_and(|c| c.is_ascii_alphanumeric()) {
+            runs.push((chars[start..i].iter().collect(), digits, international));
+        }
+    }
+    runs
+}
+
+fn full_width_digit(c: char) -> Option<u32> {
+    ('０'..='９').contains(&c).then(|| c as u32 - '０' as u32)
+}
+
+fn luhn_valid(digits: &str) -> bool {
+    let sum: u32 = digits
+        .chars()
+        .rev()
+        .filter_map(|c| c.to_digit(10))
+        .enumerate()
+        .map(|(i, d)| match (i % 2 == 1, d * 2) {
+            (true, doubled) if doubled > 9 => doubled - 9,
+            (true, doubled) => doubled,
+            (false, _) => d,
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+/// Check digit of the Individual Number (マイナンバー).
+fn my_number_valid(digits: &str) -> bool {
+    let values: Vec<u32> = digits.chars().filter_map(|c| c.to_digit(10)).collect();
+    if values.len() != 12 || values.iter().all(|&d| d == values[0]) {
+        return false;
+    }
+    let sum: u32 = values[..11]
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &p)| {
+            let n = i as u32 + 1;
+            p * if n <= 6 { n + 1 } else { n - 5 }
+        })
+        .sum();
+    let remainder = sum % 11;
+    let check = if remainder <= 1 { 0 } else { 11 - remainder };
+    values[11] == check
+}
+
+/// Japanese numbers (0 followed by 9 or 10 digits) or `+` international numbers.
+fn is_phone_number(digits: &str, international: bool) -> bool {
+    if international {
+        return (10..=15).contains(&digits.len());
+    }
+    digits.starts_with('0') && !digits.starts_with("00") && (10..=11).contains(&digits.len())
+}
+
+fn emails(text: &str) -> Vec<String> {
+    let local = |c: char| c.is_ascii_alphanumeric() || "._%+-".contains(c);
+    let domain = |c: char| c.is_ascii_alphanumeric() || ".-".contains(c);
+    let mut found = Vec::new();
+    for (at, _) in text.match_indices('@') {
+        let start = text[..at]
+            .char_indices()
+            .rev()
+            .take_while(|&(_, c)| local(c))
+            .last()
+            .map(|(i, _)| i)
+            .unwrap_or(at);
+        let end = text[at + 1..]
+            .char_indices()
+            .take_while(|&(_, c)| domain(c))
+            .last()
+            .map(|(i, c)| at + 1 + i + c.len_utf8())
+            .unwrap_or(at + 1);
+        let host = text[at + 1..end].trim_end_matches(['.', '-']);
+        if start < at && host.contains('.') && !host.starts_with('.') {
+            found.push(format!("{}@{}", &text[start..at], host));
+        }
+    }
+    found
+}
+
+fn mask(value: &str) -> String {
+    let count = value.chars().count();
+    value
+        .chars()
+        .enumerate()
+        .map(|(i, c)| {
+            if i + 4 < count && c.is_alphanumeric() {
+                '*'
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    fn kinds(text: &str) -> Vec<Kind> {
+        scan(text).into_iter().map(|f| f.kind).collect()
+    }
+
+    #[test]
+    fn test_card_numbers_need_luhn() {
+        assert_eq!(kinds("Card: 4111 1111 1111 1111"), vec![Kind::CardNumber]);
+        assert_eq!(kinds("Card: 4111-1111-1111-1112"), vec![]);
+        assert_eq!(scan("4111111111111111")[0].masked, "************1111");
+    }
+
+    #[test]
+    fn test_my_number_check_digit() {
+        assert_eq!(kinds("マイナンバー: 1234 5678 9018"), vec![Kind::MyNumber]);
+        assert_eq!(kinds("マイナンバー: 1234 5678 9012"), vec![]);
+        assert_eq!(kinds("１２３４５６７８９０１８"), vec![Kind::MyNumber]);
+    }
+
+    #[test]
+    fn test_phone_numbers_and_emails() {
+        assert_eq!(
+            kinds("TEL 03-1234-5678 / 090-1234-5678 / +81 3 1234 5678"),
+            vec![Kind::PhoneNumber; 3]
+        );
+        assert_eq!(kinds("Invoice 2026-01-31, order 12345"), vec![]);
+        assert_eq!(
+            emails("mail yamada.taro@example.co.jp."),
+            vec!["yamada.taro@example.co.jp"]
+        );
+    }
+
+    #[test]
+    fn test_docx_text_is_scanned() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("memo.docx");
+        let mut writer = zip::ZipWriter::new(std::fs::File::create(&path)?);
+        writer.start_file::<_, ()>("word/document.xml", Default::default())?;
+        writer.write_all(
+            b"<w:document><w:body><w:p><w:r><w:t>4111 1111 </w:t></w:r>\
+              <w:r><w:t>1111 1111</w:t></w:r></w:p><w:p><w:r><w:t>R&amp;D</w:t></w:r></w:p>\
+              </w:body></w:document>",
+        )?;
+        writer.finish()?;
+
+        let text = extract_text(&path)?.unwrap();
+        assert!(text.contains("R&D"));
+
+        let policy = DlpPolicy {
+            card_numbers: Action::Block,
+            ..Default::default()
+        };
+        let files = vec![path.to_string_lossy().into_owned()];
+        assert!(policy.enforce(&files).is_err());
+
+        let policy = DlpPolicy {
+            card_numbers: Action::Warn,
+            ..Default::default()
+        };
+        assert_eq!(policy.enforce(&files)?.len(), 1);
+        Ok(())
+    }
+}
@@ -1,3 +1,16 @@
+use super::dkim::DkimConfig;
+use super::sender::{self, Headers, MB};
+use super::sendgrid::SendGridOptions;
+use super::smime::SmimeConfig;
+use super::smtp::SmtpConfig;
+use crate::address::Mailbox;
+use crate::config::Settings;
+use crate::credentials;
+use crate::output::say;
+
+/// SendGrid rejects messages over 30 MB, attachments included.
+pub const MAX_MESSAGE_SIZE: u64 = 30 * MB;
+
 #[derive(Debug, Clone)]
 pub enum SendGridProtocol {
     Rest,
@@ -7,9 +20,20 @@ pub enum SendGridProtocol {
 #[derive(Debug, Clone)]
 pub struct SendGridConfig {
     pub api_key: String,
-    #[allow(dead_code)]
     pub protocol: SendGridProtocol,
     pub from_address: String,
+    /// Largest message the provider, or the `max_message_mb` of the profile, accepts.
+    pub max_message_size: u64,
+    /// A relay of our own, used instead of SendGrid's; no API key is needed then.
+    pub relay: Option<SmtpConfig>,
+    pub smime: Option<SmimeConfig>,
+    pub dkim: Option<DkimConfig>,
+    pub reply_to: Option<String>,
+    /// Added to every email, through the REST API as well as over SMTP.
+    pub headers: Headers,
+    pub options: SendGridOptions,
+    /// Validate requests with SendGrid's sandbox mode instead of delivering them.
+    pub sandbox: bool,
 }
 
 impl SendGridConfig {
@@ -17,10 +41,15 @@ impl SendGridConfig {
         api_key: Option<String>,
         protocol: Option<String>,
         from_address: Option<String>,
+        relay: Option<SmtpConfig>,
     ) -> anyhow::Result<Self> {
-        let api_key = api_key.ok_or_else(|| anyhow::anyhow!("SendGrid API key is not set"))?;
+        let api_key = match (api_key, &relay) {
+            (Some(api_key), _) => api_key,
+            (None, Some(_)) => String::new(),
+            (None, None) => return Err(anyhow::anyhow!("SendGrid API key is not set")),
+        };
 
-        if api_key.trim().is_empty() {
+        if api_key.trim().is_empty() && relay.is_none() {
             return Err(anyhow::anyhow!("SendGrid API key cannot be empty"));
         }
 
@@ -29,6 +58,7 @@ impl SendGridConfig {
             .to_lowercase()
             .as_str()
         {
+            _ if relay.is_some() => SendGridProtocol::Smtp,
             "smtp" => SendGridProtocol::Smtp,
             _ => SendGridProtocol::Rest,
         };
@@ -36,26 +66,106 @@ impl SendGridConfig {
         let from_address =
             from_address.ok_or_else(|| anyhow::anyhow!("From address is not set"))?;
 
-        println!("SendGrid Configuration:");
-        println!("  API Key: {}", api_key);
-        println!("  Protocol: {:?}", protocol);
-        println!("  From Address: {}", from_address);
+        say!("SendGrid Configuration:");
+        match &relay {
+            Some(relay) => say!("  SMTP Relay: {}:{}", relay.host, relay.port),
+            None => {
+                say!("  API Key: {}", crate::config::mask_secret(&api_key));
+                say!("  Protocol: {:?}", protocol);
+            }
+        }
+        say!("  From Address: {}", from_address);
 
         Ok(Self {
             api_key,
             protocol,
             from_address,
+            max_message_size: MAX_MESSAGE_SIZE,
+            relay,
+            smime: None,
+            dkim: None,
+            reply_to: None,
+            headers: Headers::new(),
+            options: SendGridOptions::default(),
+            sandbox: false,
         })
     }
 
-    pub fn from_env() -> anyhow::Result<Self> {
-        dotenv::dotenv().ok(); // .env ファイルを読み込み
+    /// Environment variables take precedence over the values of the selected profile.
+    pub fn from_env(settings: &Settings) -> anyhow::Result<Self> {
+        let (api_key, protocol, from_address) = Self::env_values(settings)?;
+        let relay = settings.profile.smtp.clone();
+        let mut config = Self::from_values(api_key, protocol, from_address, relay)?;
+        if let Some(mb) = settings.profile.max_message_mb {
+            config.max_message_size = config.max_message_size.min(mb.saturating_mul(MB));
+        }
+        let profile = &settings.profile;
+        if let Some(name) = &profile.from_name {
+            let mut mailbox = Mailbox::parse(&config.from_address)?;
+            mailbox.name = Some(name.clone()).filter(|n| !n.trim().is_empty());
+            config.from_address = mailbox.to_string();
+        }
+        config.reply_to = profile
+            .reply_to
+            .as_deref()
+            .map(|reply_to| Mailbox::parse(reply_to).map(|m| m.to_string()))
+            .transpose()?;
+        sender::check_headers(&profile.headers)?;
+        config.headers = profile.headers.clone();
+        if let Some(options) = &profile.sendgrid {
+            options.check()?;
+            config.options = options.clone();
+        }
+        config.smime = settings.profile.smime.clone();
+        config.dkim = settings.profile.dkim.clone();
+        config.sandbox = profile.sandbox.unwrap_or(false);
+        if matches!(config.protocol, SendGridProtocol::Smtp) && config.sandbox {
+            return Err(anyhow::anyhow!(
+                "Sandbox mode needs provider = \"rest\": only the REST API can validate without delivering"
+            ));
+        }
+        if matches!(config.protocol, SendGridProtocol::Smtp) && profile.sendgrid.is_some() {
+            return Err(anyhow::anyhow!(
+                "[sendgrid] options need provider = \"rest\": they are set through the REST API"
+            ));
+        }
+        if matches!(config.protocol, SendGridProtocol::Rest) {
+            if config.smime.is_some() {
+                return Err(anyhow::anyhow!(
+                    "S/MIME needs provider = \"smtp\": the SendGrid REST API does not accept raw MIME"
+                ));
+            }
+            if config.dkim.is_some() {
+                return Err(anyhow::anyhow!(
+                    "DKIM signing needs provider = \"smtp\" or an [smtp] relay; SendGrid signs REST API mail itself"
+                ));
+            }
+        }
+        Ok(config)
+    }
 
-        let api_key = std::env::var("SENDGRID_API_KEY").ok();
-        let protocol = std::env::var("SENDGRID_PROTOCOL").ok();
-        let from_address = std::env::var("EMAIL_FROM_ADDRESS").ok();
+    /// Returns the raw `(api_key, protocol, from_address)` values without validating them.
+    /// When `SENDGRID_API_KEY` is unset the key is looked up in the credential store,
+    /// under the profile's account and then the default one.
+    pub fn env_values(
+        settings: &Settings,
+    ) -> anyhow::Result<(Option<String>, Option<String>, Option<String>)> {
+        dotenv::dotenv().ok(); // .env ファイルを読み込み
 
-        Self::from_values(api_key, protocol, from_address)
+        let profile = &settings.profile;
+        let api_key = match std::env::var("SENDGRID_API_KEY").ok() {
+            Some(api_key) => Some(api_key),
+            None => credentials::load_api_key(settings.profile_name.as_deref())?
+                .map(|(secret, _)| secret),
+        };
+        let protocol = std::env::var("SENDGRID_PROTOCOL")
+            .ok()
+            .or_else(|| profile.provider.clone());
+        let from_address = std::env::var("EMAIL_FROM_ADDRESS")
+            .ok()
+            .or_else(|| profile.from_address.clone());
+
+        Ok((api_key, protocol, from_address))
     }
 }
 
@@ -69,6 +179,7 @@ mod tests {
             Some("test_api_key".to_string()),
             Some("rest".to_string()),
             Some("foo@example.com".to_string()),
+            None,
         )
         .unwrap();
         assert!(matches!(config.protocol, SendGridProtocol::Rest));
@@ -80,6 +191,7 @@ mod tests {
             Some("test_api_key".to_string()),
             Some("smtp".to_string()),
             Some("foo@example.com".to_string()),
+            None,
         )
         .unwrap();
         assert!(matches!(config.protocol, SendGridProtocol::Smtp));
@@ -91,6 +203,7 @@ mod tests {
             Some("test_api_key".to_string()),
             None,
             Some("foo@example.com".to_string()),
+            None,
         )
         .unwrap();
         assert!(matches!(config.protocol, SendGridProtocol::Rest));
@@ -98,16 +211,31 @@ mod tests {
 
     #[test]
     fn test_config_without_api_key() {
-        let result = SendGridConfig::from_values(None, None, None);
+        let result = SendGridConfig::from_values(None, None, None, None);
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_config_with_own_relay() {
+        let relay: SmtpConfig = toml::from_str("host = \"mail.example.com\"").unwrap();
+        let config = SendGridConfig::from_values(
+            None,
+            Some("rest".to_string()),
+            Some("foo@example.com".to_string()),
+            Some(relay),
+        )
+        .unwrap();
+        assert!(matches!(config.protocol, SendGridProtocol::Smtp));
+        assert_eq!(config.relay.unwrap().port, 587);
+    }
+
     #[test]
     fn test_config_with_invalid_protocol() {
         let config = SendGridConfig::from_values(
             Some("test_api_key".to_string()),
             Some("invalid_protocol".to_string()),
             Some("foo@example.com".to_string()),
+            None,
         )
         .unwrap();
         assert!(matches!(config.protocol, SendGridProtocol::Rest));
@@ -0,0 +1,352 @@
+use anyhow::Context;
+use base64::Engine;
+use openssl::hash::{hash, MessageDigest};
+use openssl::pkey::{Id, PKey, Private};
+use openssl::sign::Signer;
+use serde::Deserialize;
+use std::path::PathBuf;
+
+/// `[profiles.NAME.dkim]`: sign messages sent through the SMTP relay.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DkimConfig {
+    /// The `d=` domain, usually that of the from address.
+    pub domain: String,
+    /// The key is published at `SELECTOR._domainkey.DOMAIN`.
+    pub selector: String,
+    /// RSA or Ed25519 private key in PEM format.
+    pub key: PathBuf,
+    #[serde(default = "default_headers")]
+    pub headers: Vec<String>,
+}
+
+fn default_headers() -> Vec<String> {
+    [
+        "From",
+        "To",
+        "Cc",
+        "Reply-To",
+        "Subject",
+        "Date",
+        "Message-ID",
+        "MIME-Version",
+        "Content-Type",
+    ]
+    .map(String::from)
+    .to_vec()
+}
+
+pub struct Dkim {
+    domain: String,
+    selector: String,
+    headers: Vec<String>,
+    key: PKey<Private>,
+}
+
+impl Dkim {
+    pub fn load(config: &DkimConfig) -> anyhow::Result<Self> {
+        if !config
+            .headers
+            .iter()
+            .any(|h| h.eq_ignore_ascii_case("from"))
+        {
+            return Err(anyhow::anyhow!("DKIM headers must include From"));
+        }
+        let pem = std::fs::read(&config.key)
+            .with_context(|| format!("Failed to read DKIM key: {}", config.key.display()))?;
+        let key = PKey::private_key_from_pem(&pem)
+            .with_context(|| format!("Invalid DKIM key: {}", config.key.display()))?;
+        if !matches!(key.id(), Id::RSA | Id::ED25519) {
+            return Err(anyhow::anyhow!(
+                "DKIM key must be RSA or Ed25519: {}",
+                config.key.display()
+            ));
+        }
+        Ok(Self {
+            domain: config.domain.clone(),
+            selector: config.selector.clone(),
+            headers: config.headers.clone(),
+            key,
+        })
+    }
+
+    /// Returns the message with a `DKIM-Signature` header in front.
+    pub fn sign(&self, message: &[u8]) -> anyhow::Result<Vec<u8>> {
+        self.sign_at(message, chrono::Utc::now().timestamp())
+    }
+
+    /// relaxed/relaxed canonicalization as in RFC 6376, section 3.4.
+    fn sign_at(&self, message: &[u8], timestamp: i64) -> anyhow::Result<Vec<u8>> {
+        let (fields, body) = split_message(message);
+        let body_hash = hash(MessageDigest::sha256(), &canonical_body(body))?;
+
+        // Each listed header is signed once, from the bottom up; absent ones are left out.
+        let mut used = vec![false; fields.len()];
+        let mut signed_names = Vec::new();
+        let mut data = Vec::new();
+        for name in &self.headers {
+            let found = (0..fields.len())
+                .rev()
+                .find(|&i| !used[i] && field_name(fields[i]).eq_ignore_ascii_case(name));
+            if let Some(i) = found {
+                used[i] = true;
+                signed_names.push(name.to_lowercase());
+                data.extend(canonical_header(fields[i]));
+            }
+        }
+
+        let algorithm = match self.key.id() {
+            Id::ED25519 => "ed25519-sha256",
+            _ => "rsa-sha256",
+        };
+        let b64 = base64::engine::general_purpose::STANDARD;
+        let tags = [
+            "v=1".to_string(),
+            format!("a={}", algorithm),
+            "c=relaxed/relaxed".to_string(),
+            format!("d={}", self.domain),
+            format!("s={}", self.selector),
+            format!("t={}", timestamp),
+            format!("h={}", signed_names.join(":")),
+            format!("bh={}", b64.encode(body_hash)),
+        ];
+        // The signature header itself is signed with an empty b= and without the final CRLF.
+        let unsigned = format!("{}; b=", tags.join("; "));
+        data.extend(format!("dkim-signature:{}", unsigned).bytes());
+
+        let signature = match self.key.id() {
+            // Ed25519 signs the SHA-256 hash of the data (RFC 8463).
+            Id::ED25519 => Signer::new_without_digest(&self.key)?
+                .sign_oneshot_to_vec(&hash(MessageDigest::sha256(), &data)?)?,
+            _ => {
+                let mut signer = Signer::new(MessageDigest::sha256(), &self.key)?;
+                signer.update(&data)?;
+                signer.sign_to_vec()?
+            }
+        };
+
+        // Folding between tags keeps the relaxed form identical to what was signed.
+        let mut signed = format!(
+            "DKIM-Signature: {};\r\n\tb={}\r\n",
+            tags.join(";\r\n\t"),
+            b64.encode(signature)
+        )
+        .into_bytes();
+        signed.extend_from_slice(message);
+        Ok(signed)
+    }
+}
+
+/// Header fields (each with its folded lines and final CRLF) and the body.
+fn split_message(message: &[u8]) -> (Vec<&[u8]>, &[u8]) {
+    let (header, body) = match message.windows(4).position(|w| w == b"\r\n\r\n") {
+        Some(end) => (&message[..end + 2], &message[end + 4..]),
+        None => (message, &[][..]),
+    };
+    let mut fields = Vec::new();
+    let mut start = 0;
+    for i in 0..header.len().saturating_sub(1) {
+        if &header[i..i + 2] == b"\r\n" && !matches!(header.get(i + 2), Some(b' ' | b'\t')) {
+            fields.push(&header[start..i + 2]);
+            start = i + 2;
+        }
+    }
+    (fields, body)
+}
+
+fn field_name(field: &[u8]) -> &str {
+    let end = field.iter().position(|&b| b == b':').unwrap_or(field.len());
+    std::str::from_utf8(&field[..end]).unwrap_or("").trim()
+}
+
+/// Collapses runs of spaces and tabs to one space and drops the trailing ones.
+fn relax(line: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(line.len());
+    let mut space = false;
+    for &b in line {
+        if b == b' ' || b == b'\t' {
+            space = true;
+        } else {
+            if space && !out.is_empty() {
+                out.push(b' ');
+            }
+            space = false;
+            out.push(b);
+        }
+    }
+    out
+}
+
+fn canonical_header(field: &[u8]) -> Vec<u8> {
+    let colon = field.iter().position(|&b| b == b':').unwrap_or(field.len());
+    let value: Vec<u8> = field[(colon + 1).min(field.len())..]
+        .iter()
+        .copied()
+        .filter(|&b| b != b'\r' && b != b'\n')
+        .collect();
+    let mut out = field_name(field).to_lowercase().into_bytes();
+    out.push(b':');
+    out.extend(relax(&value));
+    out.extend_from_slice(b"\r\n");
+    out
+}
+
+fn canonical_body(body: &[u8]) -> Vec<u8> {
+    let mut lines: Vec<Vec<u8>> = body
+        .split(|&b| b == b'\n')
+        .map(|line| {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            // Leading whitespace is kept as a single space, only trailing whitespace goes.
+            let mut relaxed = relax(line);
+            if !relaxed.is_empty() && matches!(line.first(), Some(b' ' | b'\t')) {
+                relaxed.insert(0, b' ');
+            }
+            relaxed
+        })
+        .collect();
+    while lines.last().is_some_and(|line| line.is_empty()) {
+        lines.pop();
+    }
+    lines
+        .iter()
+        .flat_map(|line| [line.as_slice(), b"\r\n"])
+        .flatten()
+        .copied()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mail_auth::common::parse::TxtRecordParser;
+    use mail_auth::common::verify::DomainKey;
+    use mail_auth::{
+        AuthenticatedMessage, DkimResult, MessageAuthenticator, Parameters, ResolverCache, Txt,
+    };
+    use std::borrow::Borrow;
+    use std::collections::HashMap;
+    use std::hash::Hash;
+    use std::sync::Mutex;
+    use std::time::Instant;
+    use tempfile::tempdir;
+
+    /// Stands in for DNS, holding the published `_domainkey` records.
+    #[derive(Default)]
+    struct Records(Mutex<HashMap<String, Txt>>);
+
+    impl ResolverCache<String, Txt> for Records {
+        fn get<Q>(&self, name: &Q) -> Option<Txt>
+        where
+            String: Borrow<Q>,
+            Q: Hash + Eq + ?Sized,
+        {
+            self.0.lock().unwrap().get(name).cloned()
+        }
+
+        fn remove<Q>(&self, name: &Q) -> Option<Txt>
+        where
+            String: Borrow<Q>,
+            Q: Hash + Eq + ?Sized,
+        {
+            self.0.lock().unwrap().remove(name)
+        }
+
+        fn insert(&self, key: String, value: Txt, _: Instant) {
+            self.0.lock().unwrap().insert(key, value);
+        }
+    }
+
+    const MESSAGE: &[u8] = b"From: Sender <me@example.com>\r\n\
+        To: bob@example.net\r\n\
+        Subject: Secure File Transfer -\r\n  Encrypted Document\r\n\
+        Date: Mon, 19 Oct 2026 00:00:00 +0000\r\n\
+        MIME-Version: 1.0\r\n\
+        Content-Type: text/plain; charset=utf-8\r\n\
+        \r\n\
+        Please find the attached file.  \r\n\
+        \tIndented line\r\n\
+        \r\n\
+        \r\n";
+
+    fn sign_and_verify(key: PKey<Private>, record: String, message: &[u8]) -> DkimResult {
+        let temp_dir = tempdir().unwrap();
+        let key_path = temp_dir.path().join("dkim.pem");
+        std::fs::write(&key_path, key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+        let dkim = Dkim::load(&DkimConfig {
+            domain: "example.com".to_string(),
+            selector: "ppap".to_string(),
+            key: key_path,
+            headers: default_headers(),
+        })
+        .unwrap();
+        let signed = dkim.sign(MESSAGE).unwrap();
+        assert!(signed.starts_with(b"DKIM-Signature: v=1;"));
+
+        // Tests can swap in a modified message to check that verification fails.
+        let received = if message == MESSAGE {
+            signed
+        } else {
+            let header_end = signed.len() - MESSAGE.len();
+            [&signed[..header_end], message].concat()
+        };
+        let records = Records::default();
+        records.insert(
+            "ppap._domainkey.example.com.".to_string(),
+            DomainKey::parse(record.as_bytes()).unwrap().into(),
+            Instant::now(),
+        );
+        let authenticator = MessageAuthenticator::new_cloudflare().unwrap();
+        let parsed = AuthenticatedMessage::parse(&received).unwrap();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let results = runtime
+            .block_on(authenticator.verify_dkim(Parameters::new(&parsed).with_txt_cache(&records)));
+        assert_eq!(results.len(), 1);
+        results[0].result().clone()
+    }
+
+    #[test]
+    fn test_rsa_signature_verifies() {
+        let key = PKey::from_rsa(openssl::rsa::Rsa::generate(2048).unwrap()).unwrap();
+        let b64 = base64::engine::general_purpose::STANDARD;
+        let record = format!(
+            "v=DKIM1; k=rsa; p={}",
+            b64.encode(key.public_key_to_der().unwrap())
+        );
+        assert_eq!(
+            sign_and_verify(key.clone(), record.clone(), MESSAGE),
+            DkimResult::Pass
+        );
+
+        let tampered = String::from_utf8_lossy(MESSAGE).replace("Sender", "Someone");
+        assert!(matches!(
+            sign_and_verify(key, record, tampered.as_bytes()),
+            DkimResult::Fail(_)
+        ));
+    }
+
+    #[test]
+    fn test_ed25519_signature_verifies() {
+        let key = PKey::generate_ed25519().unwrap();
+        let b64 = base64::engine::general_purpose::STANDARD;
+        let record = format!(
+            "v=DKIM1; k=ed25519; p={}",
+            b64.encode(key.raw_public_key().unwrap())
+        );
+        assert_eq!(sign_and_verify(key, record, MESSAGE), DkimResult::Pass);
+    }
+
+    #[test]
+    fn test_relaxed_canonicalization() {
+        let (fields, body) = split_message(MESSAGE);
+        assert_eq!(fields.len(), 6);
+        assert_eq!(
+            canonical_header(fields[2]),
+            b"subject:Secure File Transfer - Encrypted Document\r\n"
+        );
+        assert_eq!(
+            canonical_body(body),
+            b"Please find the attached file.\r\n Indented line\r\n"
+        );
+        assert_eq!(canonical_body(b"\r\n\r\n"), b"");
+    }
+}
@@ -0,0 +1,134 @@
+use super::sender::Headers;
+use super::smime::Smime;
+use crate::address;
+use anyhow::Context;
+use lettre::address::Envelope;
+use lettre::message::header::{
+    ContentTransferEncoding, ContentType, HeaderName, HeaderValue, MimeVersion,
+};
+use lettre::message::{Attachment, Mailbox, Message, MultiPart, SinglePart};
+
+fn mailbox(recipient: &str) -> anyhow::Result<Mailbox> {
+    let mailbox = address::Mailbox::parse(recipient)?;
+    let email = mailbox
+        .email
+        .parse()
+        .with_context(|| format!("Invalid address: {}", recipient))?;
+    Ok(Mailbox::new(mailbox.name, email))
+}
+
+/// Builds one email as raw MIME, signed and encrypted with S/MIME when `smime` is given.
+/// `headers` are added as they are, after the standard ones.
+#[allow(clippy::too_many_arguments)]
+pub fn build(
+    from: &str,
+    to: &[String],
+    cc: &[String],
+    subject: &str,
+    body: &str,
+    attachment_path: Option<&str>,
+    reply_to: Option<&str>,
+    message_id: &str,
+    headers: &Headers,
+    smime: Option<&Smime>,
+) -> anyhow::Result<(Envelope, Vec<u8>)> {
+    let text = SinglePart::plain(body.to_string());
+    let mut entity = match attachment_path {
+        Some(path) => {
+            let content = std::fs::read(path)
+                .with_context(|| format!("Failed to read attachment: {}", path))?;
+            let filename = std::path::Path::new(path)
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or("attachment.zip")
+                .to_string();
+            let content_type = if filename.ends_with(".zip") {
+                "application/zip"
+            } else {
+                "application/octet-stream"
+            };
+            MultiPart::mixed()
+                .singlepart(text)
+                .singlepart(
+                    Attachment::new(filename).body(content, ContentType::parse(content_type)?),
+                )
+                .formatted()
+        }
+        None => text.formatted(),
+    };
+    if let Some(smime) = smime {
+        let recipients: Vec<String> = to.iter().chain(cc).cloned().collect();
+        entity = smime.wrap(&entity, &recipients)?;
+    }
+
+    // Only the headers of this message are used, the body is the entity built above.
+    let mut builder = Message::builder()
+        .from(mailbox(from)?)
+        .subject(subject)
+        .message_id(Some(message_id.to_string()));
+    if let Some(reply_to) = reply_to {
+        builder = builder.reply_to(mailbox(reply_to)?);
+    }
+    for recipient in to {
+        builder = builder.to(mailbox(recipient)?);
+    }
+    for recipient in cc {
+        builder = builder.cc(mailbox(recipient)?);
+    }
+    let message = builder.body(Vec::new())?;
+    let mut fields = message.headers().clone();
+    fields.remove::<ContentType>();
+    fields.remove::<ContentTransferEncoding>();
+    fields.remove::<MimeVersion>();
+    for (name, value) in headers {
+        let name = HeaderName::new_from_ascii(name.clone())
+            .map_err(|_| anyhow::anyhow!("Invalid header name: {}", name))?;
+        fields.insert_raw(HeaderValue::new(name, value.clone()));
+    }
+
+    let mut formatted = fields.to_string().into_bytes();
+    formatted.extend_from_slice(b"MIME-Version: 1.0\r\n");
+    formatted.extend_from_slice(&entity);
+    Ok((message.envelope().clone(), formatted))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_build_with_attachment() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let archive = temp_dir.path().join("report.pdf.zip");
+        std::fs::write(&archive, b"zip bytes")?;
+
+        let (envelope, message) = build(
+            "Sender <me@example.com>",
+            &["\"山田 太郎\" <yamada@example.jp>".to_string()],
+            &["cc@example.com".to_string()],
+            "Document",
+            "Please find the attached file.",
+            archive.to_str(),
+            Some("Support <support@example.com>"),
+            "<first.ppap@example.com>",
+            &Headers::from([
+                ("In-Reply-To".to_string(), "<zero@example.com>".to_string()),
+                ("X-Classification".to_string(), "Confidential".to_string()),
+            ]),
+            None,
+        )?;
+        let message = String::from_utf8(message)?;
+        assert_eq!(envelope.to().len(), 2);
+        assert!(message.contains("From: Sender <me@example.com>\r\n"));
+        assert!(message.contains("Cc: cc@example.com\r\n"));
+        assert!(message.contains("Message-ID: <first.ppap@example.com>\r\n"));
+        assert!(message.contains("In-Reply-To: <zero@example.com>\r\n"));
+        assert!(message.contains("Reply-To: Support <support@example.com>\r\n"));
+        assert!(message.contains("X-Classification: Confidential\r\n"));
+        assert!(message.contains("MIME-Version: 1.0\r\nContent-Type: multipart/mixed"));
+        assert!(message.contains("filename=\"report.pdf.zip\""));
+        assert_eq!(message.matches("MIME-Version").count(), 1);
+        Ok(())
+    }
+}
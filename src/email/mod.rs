@@ -1,3 +1,7 @@
 pub mod config;
+pub mod dkim;
+pub mod mime;
 pub mod sender;
 pub mod sendgrid;
+pub mod smime;
+pub mod smtp;
@@ -1,3 +1,176 @@
+use crate::address;
+use chrono::{DateTime, Utc};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Mail size limits are given in decimal megabytes, the smaller unit errs on the safe side.
+pub const MB: u64 = 1_000_000;
+
+/// Room left for headers, the text body and the request envelope.
+const MESSAGE_OVERHEAD: u64 = 64 * 1024;
+
+/// Largest attachment that fits in a message of `message_limit` bytes once base64 encoded.
+/// Every 57 bytes become a 76 character line plus CRLF, as SMTP sends them.
+pub fn max_attachment_size(message_limit: u64) -> u64 {
+    message_limit.saturating_sub(MESSAGE_OVERHEAD) / 78 * 57
+}
+
+/// Extra header fields of one email, such as `In-Reply-To`.
+pub type Headers = BTreeMap<String, String>;
+
+/// A new `Message-ID` in the domain of the from address.
+pub fn new_message_id(from_address: &str) -> String {
+    let domain = address::email_of(from_address)
+        .rsplit_once('@')
+        .map(|(_, domain)| domain.to_string())
+        .unwrap_or_else(|| "localhost".to_string());
+    let id: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(24)
+        .map(char::from)
+        .collect();
+    format!("<{}.ppap@{}>", id, domain)
+}
+
+/// Makes an email a reply to `message_id`, so mail clients show both in one thread.
+pub fn reply_headers(message_id: &str) -> Headers {
+    Headers::from([
+        ("In-Reply-To".to_string(), message_id.to_string()),
+        ("References".to_string(), message_id.to_string()),
+    ])
+}
+
+/// Carries the reference code shown in the subjects.
+pub const REFERENCE_HEADER: &str = "X-PPAP-Reference";
+
+/// Headers set by ppap itself, or by SendGrid, which custom headers may not replace.
+const RESERVED_HEADERS: &[&str] = &[
+    "from",
+    "to",
+    "cc",
+    "bcc",
+    "reply-to",
+    "subject",
+    "date",
+    "message-id",
+    "in-reply-to",
+    "references",
+    "mime-version",
+    "content-type",
+    "content-transfer-encoding",
+    "dkim-signature",
+    "received",
+    "return-path",
+    "x-sg-id",
+    "x-sg-eid",
+    "x-ppap-reference",
+];
+
+/// Checks custom headers from the profile or `--header`.
+pub fn check_headers(headers: &Headers) -> anyhow::Result<()> {
+    for (name, value) in headers {
+        if name.is_empty() || !name.bytes().all(|b| b.is_ascii_graphic() && b != b':') {
+            return Err(anyhow::anyhow!("Invalid header name: {:?}", name));
+        }
+        if RESERVED_HEADERS.contains(&name.to_ascii_lowercase().as_str()) {
+            return Err(anyhow::anyhow!(
+                "Header {} is set by ppap and cannot be customized",
+                name
+            ));
+        }
+        if value.contains(['\r', '\n']) {
+            return Err(anyhow::anyhow!("Header {} must be a single line", name));
+        }
+    }
+    Ok(())
+}
+
+/// Tags an email with the reference code of its transfer.
+pub fn reference_headers(reference: &str) -> Headers {
+    Headers::from([(REFERENCE_HEADER.to_string(), reference.to_string())])
+}
+
+/// Short code shown in both subjects so the emails can be paired by eye.
+/// Letters and digits that are easily confused are left out.
+pub fn reference_code() -> String {
+    const CHARS: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+    let mut rng = rand::thread_rng();
+    (0..6)
+        .map(|_| CHARS[rng.gen_range(0..CHARS.len())] as char)
+        .collect()
+}
+
+/// What the provider said when it accepted an email, to find it in the provider's logs.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Receipt {
+    /// `sendgrid` for the REST API, otherwise the SMTP relay.
+    pub provider: String,
+    /// SendGrid's `X-Message-Id`, or the queue ID in the relay's reply.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider_message_id: Option<String>,
+    /// The `Message-ID` header of the email.
+    pub message_id: String,
+    pub accepted_at: DateTime<Utc>,
+}
+
+impl Receipt {
+    pub fn new(provider: &str, provider_message_id: Option<String>, message_id: String) -> Self {
+        Self {
+            provider: provider.to_string(),
+            provider_message_id,
+            message_id,
+            accepted_at: Utc::now(),
+        }
+    }
+}
+
 pub trait EmailSender {
-    async fn send_email(&self, to: &str, subject: &str, body: &str) -> anyhow::Result<()>;
+    async fn send_email(
+        &self,
+        to: &[String],
+        cc: &[String],
+        subject: &str,
+        body: &str,
+        headers: &Headers,
+    ) -> anyhow::Result<Receipt>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::Engine;
+
+    #[test]
+    fn test_max_attachment_size_fits_after_encoding() {
+        let limit = 10 * MB;
+        let size = max_attachment_size(limit);
+        let encoded = base64::engine::general_purpose::STANDARD.encode(vec![0u8; size as usize]);
+        let with_line_breaks = encoded.len() as u64 + encoded.len() as u64 / 76 * 2;
+        assert!(with_line_breaks + MESSAGE_OVERHEAD <= limit);
+        assert!(size > 7 * MB);
+        assert_eq!(max_attachment_size(1000), 0);
+    }
+
+    #[test]
+    fn test_check_headers() {
+        let header = |name: &str, value: &str| Headers::from([(name.into(), value.into())]);
+        assert!(check_headers(&header("X-Classification", "Confidential")).is_ok());
+        assert!(check_headers(&header("reply-to", "a@example.com")).is_err());
+        assert!(check_headers(&header("X Bad", "value")).is_err());
+        assert!(check_headers(&header("X-Note", "one\r\nBcc: b@example.com")).is_err());
+    }
+
+    #[test]
+    fn test_message_id_and_reference_code() {
+        let id = new_message_id("\"Sender\" <me@Example.com>");
+        assert!(id.starts_with('<') && id.ends_with(".ppap@example.com>"));
+        assert_ne!(id, new_message_id("me@example.com"));
+        assert_eq!(reply_headers(&id)["References"], id);
+
+        let code = reference_code();
+        assert_eq!(code.len(), 6);
+        assert!(!code.contains(['0', 'O', '1', 'I']));
+    }
 }
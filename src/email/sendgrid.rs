@@ -1,34 +1,202 @@
-use super::config::SendGridConfig;
-use super::sender::EmailSender;
+use super::config::{SendGridConfig, SendGridProtocol};
+use super::dkim::Dkim;
+use super::mime;
+use super::sender::{self, max_attachment_size, EmailSender, Headers, Receipt, MB};
+use super::smime::Smime;
+use super::smtp::SmtpRelay;
+use crate::address::Mailbox;
+use crate::output::say;
+use crate::pubkey::Keyring;
 use anyhow::Context;
 use base64::Engine;
 use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
-pub struct SendGridRestSender {
+/// `[profiles.NAME.sendgrid]`: how SendGrid tags and tracks the mail, REST API only.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SendGridOptions {
+    /// Shown in SendGrid's statistics and activity feed.
+    #[serde(default)]
+    pub categories: Vec<String>,
+    /// Returned in event webhooks, e.g. `{ team = "legal" }`.
+    #[serde(default)]
+    pub custom_args: BTreeMap<String, String>,
+    /// Unsubscribe group the mail belongs to.
+    pub asm_group_id: Option<u32>,
+    #[serde(default)]
+    pub asm_groups_to_display: Vec<u32>,
+    pub ip_pool: Option<String>,
+    /// Leave click and open tracking to the account settings. When off, both are disabled,
+    /// so links in the password email are never rewritten.
+    #[serde(default)]
+    pub tracking: bool,
+}
+
+impl SendGridOptions {
+    /// SendGrid's limits on categories.
+    pub fn check(&self) -> anyhow::Result<()> {
+        if self.categories.len() > 10 {
+            return Err(anyhow::anyhow!("SendGrid accepts at most 10 categories"));
+        }
+        if let Some(category) = self
+            .categories
+            .iter()
+            .find(|c| c.is_empty() || c.len() > 255 || !c.is_ascii())
+        {
+            return Err(anyhow::anyhow!(
+                "Invalid SendGrid category {:?}: use 1 to 255 ASCII characters",
+                category
+            ));
+        }
+        if !self.asm_groups_to_display.is_empty() && self.asm_group_id.is_none() {
+            return Err(anyhow::anyhow!("asm_groups_to_display needs asm_group_id"));
+        }
+        Ok(())
+    }
+}
+
+/// Provider name in the receipts of REST API mail.
+const PROVIDER: &str = "sendgrid";
+
+/// SendGrid's ID for the message, used in its activity feed and event webhooks.
+fn provider_message_id(response: &reqwest::Response) -> Option<String> {
+    response
+        .headers()
+        .get("x-message-id")
+        .and_then(|id| id.to_str().ok())
+        .map(str::to_string)
+}
+
+/// `headers` of the REST API request, with the `Message-ID` SendGrid should use.
+fn with_message_id(headers: &Headers, message_id: &str) -> Headers {
+    let mut headers = headers.clone();
+    headers.insert("Message-ID".to_string(), message_id.to_string());
+    headers
+}
+
+/// Sends through the REST API, or as raw MIME through the SMTP relay with `provider = "smtp"`.
+pub struct SendGridSender {
     api_key: String,
     from_address: String,
+    max_message_size: u64,
     client: reqwest::Client,
+    smtp: Option<SmtpRelay>,
+    smime: Option<Smime>,
+    dkim: Option<Dkim>,
+    reply_to: Option<String>,
+    headers: Headers,
+    options: SendGridOptions,
+    sandbox: bool,
 }
 
 #[derive(Serialize)]
 struct SendGridMail {
     personalizations: Vec<Personalization>,
     from: EmailAddress,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_to: Option<EmailAddress>,
     subject: String,
     content: Vec<Content>,
     #[serde(skip_serializing_if = "Option::is_none")]
     attachments: Option<Vec<Attachment>>,
+    #[serde(skip_serializing_if = "Headers::is_empty")]
+    headers: Headers,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    categories: Vec<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    custom_args: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    asm: Option<Asm>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ip_pool_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tracking_settings: Option<TrackingSettings>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mail_settings: Option<MailSettings>,
+}
+
+/// SendGrid validates a request in sandbox mode but does not deliver it.
+#[derive(Serialize)]
+struct MailSettings {
+    sandbox_mode: Enable,
+}
+
+#[derive(Serialize)]
+struct Asm {
+    group_id: u32,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    groups_to_display: Vec<u32>,
+}
+
+#[derive(Serialize)]
+struct TrackingSettings {
+    click_tracking: ClickTracking,
+    open_tracking: Enable,
+}
+
+#[derive(Serialize)]
+struct ClickTracking {
+    enable: bool,
+    enable_text: bool,
+}
+
+#[derive(Serialize)]
+struct Enable {
+    enable: bool,
+}
+
+impl TrackingSettings {
+    fn disabled() -> Self {
+        Self {
+            click_tracking: ClickTracking {
+                enable: false,
+                enable_text: false,
+            },
+            open_tracking: Enable { enable: false },
+        }
+    }
 }
 
 #[derive(Serialize)]
 struct Personalization {
     to: Vec<EmailAddress>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    cc: Vec<EmailAddress>,
+}
+
+impl Personalization {
+    fn new(to: &[String], cc: &[String]) -> Self {
+        let addresses = |list: &[String]| list.iter().map(|r| EmailAddress::parse(r)).collect();
+        Self {
+            to: addresses(to),
+            cc: addresses(cc),
+        }
+    }
 }
 
 #[derive(Serialize)]
 struct EmailAddress {
     email: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+}
+
+impl EmailAddress {
+    /// Splits `Name <email>` into SendGrid's `name` and `email` fields.
+    fn parse(recipient: &str) -> Self {
+        match Mailbox::parse(recipient) {
+            Ok(mailbox) => Self {
+                email: mailbox.email,
+                name: mailbox.name,
+            },
+            Err(_) => Self {
+                email: recipient.to_string(),
+                name: None,
+            },
+        }
+    }
 }
 
 #[derive(Serialize)]
@@ -46,13 +214,199 @@ struct Attachment {
     disposition: String,
 }
 
-impl SendGridRestSender {
-    pub fn new(config: &SendGridConfig) -> Self {
-        Self {
+impl SendGridSender {
+    pub fn new(config: &SendGridConfig) -> anyhow::Result<Self> {
+        let smtp = match (&config.relay, &config.protocol) {
+            (Some(relay), _) => Some(SmtpRelay::new(relay)?),
+            (None, SendGridProtocol::Smtp) => Some(SmtpRelay::sendgrid(&config.api_key)?),
+            (None, SendGridProtocol::Rest) => None,
+        };
+        let smime = config.smime.as_ref().map(Smime::load).transpose()?;
+        let dkim = config.dkim.as_ref().map(Dkim::load).transpose()?;
+        Ok(Self {
             api_key: config.api_key.clone(),
             from_address: config.from_address.clone(),
+            max_message_size: config.max_message_size,
             client: reqwest::Client::new(),
+            smtp,
+            smime,
+            dkim,
+            reply_to: config.reply_to.clone(),
+            headers: config.headers.clone(),
+            options: config.options.clone(),
+            sandbox: config.sandbox,
+        })
+    }
+
+    /// The profile's headers with those of this email on top.
+    fn headers(&self, headers: &Headers) -> Headers {
+        let mut merged = self.headers.clone();
+        merged.extend(headers.clone());
+        merged
+    }
+
+    /// The REST API request for one email; `headers` include its `Message-ID`.
+    fn mail(
+        &self,
+        to: &[String],
+        cc: &[String],
+        subject: &str,
+        body: &str,
+        attachments: Option<Vec<Attachment>>,
+        headers: Headers,
+    ) -> SendGridMail {
+        let options = &self.options;
+        let mut custom_args = options.custom_args.clone();
+        if let Some(reference) = headers.get(sender::REFERENCE_HEADER) {
+            custom_args.insert("ppap_reference".to_string(), reference.clone());
+        }
+        SendGridMail {
+            personalizations: vec![Personalization::new(to, cc)],
+            from: EmailAddress::parse(&self.from_address),
+            reply_to: self.reply_to.as_deref().map(EmailAddress::parse),
+            subject: subject.to_string(),
+            content: vec![Content {
+                r#type: "text/plain".to_string(),
+                value: body.to_string(),
+            }],
+            attachments,
+            headers,
+            categories: options.categories.clone(),
+            custom_args,
+            asm: options.asm_group_id.map(|group_id| Asm {
+                group_id,
+                groups_to_display: options.asm_groups_to_display.clone(),
+            }),
+            ip_pool_name: options.ip_pool.clone(),
+            tracking_settings: (!options.tracking).then(TrackingSettings::disabled),
+            mail_settings: self.sandbox.then_some(MailSettings {
+                sandbox_mode: Enable { enable: true },
+            }),
+        }
+    }
+
+    /// Whether SendGrid only validates the emails.
+    pub fn is_sandbox(&self) -> bool {
+        self.sandbox
+    }
+
+    /// S/MIME certificates from the address book.
+    pub fn add_certificates(&mut self, keys: &Keyring) {
+        if let Some(smime) = &mut self.smime {
+            smime.add_certificates(keys);
+        }
+    }
+
+    /// Largest attachment per email, also honoring the recipients' own limit in MB.
+    pub fn max_attachment_size(&self, recipient_limit_mb: Option<u64>) -> u64 {
+        let limit = recipient_limit_mb
+            .map(|mb| mb.saturating_mul(MB))
+            .map_or(self.max_message_size, |limit| {
+                limit.min(self.max_message_size)
+            });
+        match &self.smime {
+            // The encrypted message is base64 encoded a second time.
+            Some(smime) if smime.encrypts() => max_attachment_size(max_attachment_size(limit)),
+            _ => max_attachment_size(limit),
+        }
+    }
+
+    /// Sends one email through the SMTP relay, with S/MIME and DKIM if configured.
+    #[allow(clippy::too_many_arguments)]
+    async fn send_raw(
+        &self,
+        smtp: &SmtpRelay,
+        to: &[String],
+        cc: &[String],
+        subject: &str,
+        body: &str,
+        attachment_path: Option<&str>,
+        message_id: &str,
+        headers: &Headers,
+    ) -> anyhow::Result<Option<String>> {
+        say!("Sending email via SMTP relay {}...", smtp.host());
+        say!("From: {}", self.from_address);
+        say!("To: {}", to.join(", "));
+        if !cc.is_empty() {
+            say!("Cc: {}", cc.join(", "));
+        }
+        say!("Subject: {}", subject);
+        if let Some(path) = attachment_path {
+            say!("Attachment: {}", path);
         }
+        if let Some(smime) = &self.smime {
+            say!(
+                "S/MIME: {}",
+                if smime.encrypts() {
+                    "signed and encrypted"
+                } else {
+                    "signed"
+                }
+            );
+        }
+
+        let (envelope, mut message) = mime::build(
+            &self.from_address,
+            to,
+            cc,
+            subject,
+            body,
+            attachment_path,
+            self.reply_to.as_deref(),
+            message_id,
+            &self.headers(headers),
+            self.smime.as_ref(),
+        )?;
+        if let Some(dkim) = &self.dkim {
+            message = dkim.sign(&message)?;
+        }
+        let queue_id = smtp.send(&envelope, &message).await?;
+        say!("Email sent successfully!");
+        Ok(queue_id)
+    }
+
+    /// Sends each part of a split archive in its own email, numbered in the subject.
+    /// A single part is sent as is. The receipt of each email is pushed to `receipts` as
+    /// soon as it is sent, so parts delivered before a failure are still reported; later
+    /// parts reply to the first one.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn send_archive(
+        &self,
+        to: &[String],
+        cc: &[String],
+        subject: &str,
+        body: &str,
+        parts: &[String],
+        headers: &Headers,
+        receipts: &mut Vec<Receipt>,
+    ) -> anyhow::Result<()> {
+        let first = receipts.len();
+        let [archive] = parts else {
+            for (i, part) in parts.iter().enumerate() {
+                let subject = format!("{} ({}/{})", subject, i + 1, parts.len());
+                let body = format!(
+                    "{}\n\nThis is part {} of {}. Save all parts in one folder and open the .001 \
+                     file with 7-Zip or `ppap unzip`.",
+                    body,
+                    i + 1,
+                    parts.len()
+                );
+                let mut headers = headers.clone();
+                if let Some(first) = receipts.get(first) {
+                    headers.extend(sender::reply_headers(&first.message_id));
+                }
+                receipts.push(
+                    self.send_email_with_attachment(to, cc, &subject, &body, part, &headers)
+                        .await?,
+                );
+            }
+            return Ok(());
+        };
+        receipts.push(
+            self.send_email_with_attachment(to, cc, subject, body, archive, headers)
+                .await?,
+        );
+        Ok(())
     }
 
     fn create_headers(&self) -> HeaderMap {
@@ -68,16 +422,37 @@ impl SendGridRestSender {
 
     pub async fn send_email_with_attachment(
         &self,
-        to: &str,
+        to: &[String],
+        cc: &[String],
         subject: &str,
         body: &str,
         attachment_path: &str,
-    ) -> anyhow::Result<()> {
-        println!("Sending email with attachment via SendGrid REST API...");
-        println!("From: {}", self.from_address);
-        println!("To: {}", to);
-        println!("Subject: {}", subject);
-        println!("Attachment: {}", attachment_path);
+        headers: &Headers,
+    ) -> anyhow::Result<Receipt> {
+        let message_id = sender::new_message_id(&self.from_address);
+        if let Some(smtp) = &self.smtp {
+            let queue_id = self
+                .send_raw(
+                    smtp,
+                    to,
+                    cc,
+                    subject,
+                    body,
+                    Some(attachment_path),
+                    &message_id,
+                    headers,
+                )
+                .await?;
+            return Ok(Receipt::new(smtp.host(), queue_id, message_id));
+        }
+        say!("Sending email with attachment via SendGrid REST API...");
+        say!("From: {}", self.from_address);
+        say!("To: {}", to.join(", "));
+        if !cc.is_empty() {
+            say!("Cc: {}", cc.join(", "));
+        }
+        say!("Subject: {}", subject);
+        say!("Attachment: {}", attachment_path);
 
         // Read file and encode to base64
         let file_content = std::fs::read(attachment_path)
@@ -107,35 +482,27 @@ impl SendGridRestSender {
             disposition: "attachment".to_string(),
         };
 
-        let mail = SendGridMail {
-            personalizations: vec![Personalization {
-                to: vec![EmailAddress {
-                    email: to.to_string(),
-                }],
-            }],
-            from: EmailAddress {
-                email: self.from_address.clone(),
-            },
-            subject: subject.to_string(),
-            content: vec![Content {
-                r#type: "text/plain".to_string(),
-                value: body.to_string(),
-            }],
-            attachments: Some(vec![attachment]),
-        };
+        let mail = self.mail(
+            to,
+            cc,
+            subject,
+            body,
+            Some(vec![attachment]),
+            with_message_id(&self.headers(headers), &message_id),
+        );
 
-        println!("Request headers:");
+        say!("Request headers:");
         let headers = self.create_headers();
         for (key, value) in headers.iter() {
             if key == "Authorization" {
-                println!("  {}: Bearer **********", key);
+                say!("  {}: Bearer **********", key);
             } else {
-                println!("  {}: {:?}", key, value);
+                say!("  {}: {:?}", key, value);
             }
         }
 
         // Note: Don't print full JSON with base64 content (too large)
-        println!("Request body: [Mail with attachment - omitted for brevity]");
+        say!("Request body: [Mail with attachment - omitted for brevity]");
 
         let response = self
             .client
@@ -146,60 +513,75 @@ impl SendGridRestSender {
             .await?;
 
         let status = response.status();
-        println!("\nResponse status: {}", status);
+        say!("\nResponse status: {}", status);
+        let receipt = Receipt::new(PROVIDER, provider_message_id(&response), message_id);
 
-        if status.is_success() {
-            println!("Email with attachment sent successfully!");
-            Ok(())
+        if status.is_success() && self.sandbox {
+            say!(
+                "Sandbox mode: SendGrid validated the email with attachment, it was not delivered."
+            );
+            Ok(receipt)
+        } else if status.is_success() {
+            say!("Email with attachment sent successfully!");
+            Ok(receipt)
         } else {
             let error_body = response.text().await?;
             let error = format!(
                 "Failed to send email with attachment: Status: {}, Body: {}",
                 status, error_body
             );
-            println!("Error: {}", error);
+            say!("Error: {}", error);
             Err(anyhow::anyhow!(error))
         }
     }
 }
 
-impl EmailSender for SendGridRestSender {
-    async fn send_email(&self, to: &str, subject: &str, body: &str) -> anyhow::Result<()> {
-        println!("Sending email via SendGrid REST API...");
-        println!("From: {}", self.from_address);
-        println!("To: {}", to);
-        println!("Subject: {}", subject);
+impl EmailSender for SendGridSender {
+    async fn send_email(
+        &self,
+        to: &[String],
+        cc: &[String],
+        subject: &str,
+        body: &str,
+        headers: &Headers,
+    ) -> anyhow::Result<Receipt> {
+        let message_id = sender::new_message_id(&self.from_address);
+        if let Some(smtp) = &self.smtp {
+            let queue_id = self
+                .send_raw(smtp, to, cc, subject, body, None, &message_id, headers)
+                .await?;
+            return Ok(Receipt::new(smtp.host(), queue_id, message_id));
+        }
+        say!("Sending email via SendGrid REST API...");
+        say!("From: {}", self.from_address);
+        say!("To: {}", to.join(", "));
+        if !cc.is_empty() {
+            say!("Cc: {}", cc.join(", "));
+        }
+        say!("Subject: {}", subject);
 
-        let mail = SendGridMail {
-            personalizations: vec![Personalization {
-                to: vec![EmailAddress {
-                    email: to.to_string(),
-                }],
-            }],
-            from: EmailAddress {
-                email: self.from_address.clone(),
-            },
-            subject: subject.to_string(),
-            content: vec![Content {
-                r#type: "text/plain".to_string(),
-                value: body.to_string(),
-            }],
-            attachments: None,
-        };
+        let mail = self.mail(
+            to,
+            cc,
+            subject,
+            body,
+            None,
+            with_message_id(&self.headers(headers), &message_id),
+        );
 
-        println!("Request headers:");
+        say!("Request headers:");
         let headers = self.create_headers();
         for (key, value) in headers.iter() {
             if key == "Authorization" {
-                println!("  {}: Bearer **********", key);
+                say!("  {}: Bearer **********", key);
             } else {
-                println!("  {}: {:?}", key, value);
+                say!("  {}: {:?}", key, value);
             }
         }
 
-        println!("Request body (sanitized):");
+        say!("Request body (sanitized):");
         if let Ok(json) = serde_json::to_string_pretty(&mail) {
-            println!("{}", json);
+            say!("{}", json);
         }
 
         let response = self
@@ -211,23 +593,216 @@ impl EmailSender for SendGridRestSender {
             .await?;
 
         let status = response.status();
-        println!("\nResponse status: {}", status);
-        println!("Response headers:");
+        say!("\nResponse status: {}", status);
+        say!("Response headers:");
         for (key, value) in response.headers() {
-            println!("  {}: {:?}", key, value);
+            say!("  {}: {:?}", key, value);
         }
+        let receipt = Receipt::new(PROVIDER, provider_message_id(&response), message_id);
 
-        if status.is_success() {
-            println!("Email sent successfully!");
-            Ok(())
+        if status.is_success() && self.sandbox {
+            say!("Sandbox mode: SendGrid validated the email, it was not delivered.");
+            Ok(receipt)
+        } else if status.is_success() {
+            say!("Email sent successfully!");
+            Ok(receipt)
         } else {
             let error_body = response.text().await?;
             let error = format!(
                 "Failed to send email: Status: {}, Body: {}",
                 status, error_body
             );
-            println!("Error: {}", error);
+            say!("Error: {}", error);
             Err(anyhow::anyhow!(error))
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sender(options: SendGridOptions) -> SendGridSender {
+        SendGridSender::new(&SendGridConfig {
+            api_key: "test_api_key".to_string(),
+            protocol: SendGridProtocol::Rest,
+            from_address: "Sender <me@example.com>".to_string(),
+            max_message_size: crate::email::config::MAX_MESSAGE_SIZE,
+            relay: None,
+            smime: None,
+            dkim: None,
+            reply_to: Some("Support <support@example.com>".to_string()),
+            headers: Headers::new(),
+            options,
+            sandbox: false,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_mail_json_carries_threading_headers() -> anyhow::Result<()> {
+        let mut headers = sender::reply_headers("<first.ppap@example.com>");
+        headers.insert(sender::REFERENCE_HEADER.to_string(), "ABC234".to_string());
+        let mail = sender(SendGridOptions::default()).mail(
+            &["bob@example.net".to_string()],
+            &[],
+            "Password [Ref ABC234]",
+            "Password: secret",
+            None,
+            with_message_id(&headers, "<second.ppap@example.com>"),
+        );
+        let json = serde_json::to_value(&mail)?;
+        assert_eq!(json["headers"]["Message-ID"], "<second.ppap@example.com>");
+        assert_eq!(json["headers"]["In-Reply-To"], "<first.ppap@example.com>");
+        assert_eq!(json["headers"]["References"], "<first.ppap@example.com>");
+        assert_eq!(json["from"]["name"], "Sender");
+        assert_eq!(json["reply_to"]["email"], "support@example.com");
+        assert_eq!(json["custom_args"]["ppap_reference"], "ABC234");
+        // Tracking is off unless the profile leaves it to the account.
+        assert_eq!(json["tracking_settings"]["click_tracking"]["enable"], false);
+        assert_eq!(json["tracking_settings"]["open_tracking"]["enable"], false);
+        assert!(json.get("categories").is_none());
+        assert!(json.get("mail_settings").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_mail_json_carries_sendgrid_options() -> anyhow::Result<()> {
+        let options: SendGridOptions = toml::from_str(
+            r#"
+            categories = ["ppap", "legal"]
+            custom_args = { team = "legal" }
+            asm_group_id = 42
+            ip_pool = "transactional"
+            tracking = true
+            "#,
+        )?;
+        options.check()?;
+        let mut sender = sender(options);
+        sender.sandbox = true;
+        let mail = sender.mail(
+            &["bob@example.net".to_string()],
+            &[],
+            "Document",
+            "Please find the attached file.",
+            None,
+            Headers::new(),
+        );
+        let json = serde_json::to_value(&mail)?;
+        assert_eq!(json["categories"], serde_json::json!(["ppap", "legal"]));
+        assert_eq!(json["custom_args"]["team"], "legal");
+        assert_eq!(json["asm"], serde_json::json!({ "group_id": 42 }));
+        assert_eq!(json["ip_pool_name"], "transactional");
+        assert!(json.get("tracking_settings").is_none());
+        assert_eq!(json["mail_settings"]["sandbox_mode"]["enable"], true);
+
+        let too_many = SendGridOptions {
+            categories: (0..11).map(|i| i.to_string()).collect(),
+            ..Default::default()
+        };
+        assert!(too_many.check().is_err());
+        Ok(())
+    }
+
+    /// Relay that queues the first message and rejects every later one.
+    async fn relay_rejecting_second(listener: tokio::net::TcpListener) -> anyhow::Result<()> {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+        let messages = Arc::new(AtomicUsize::new(0));
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let messages = messages.clone();
+            tokio::spawn(async move {
+                let (reader, mut writer) = stream.into_split();
+                let mut reader = BufReader::new(reader);
+                writer.write_all(b"220 localhost ESMTP\r\n").await?;
+                let mut in_data = false;
+                loop {
+                    let mut line = Vec::new();
+                    if reader.read_until(b'\n', &mut line).await? == 0 {
+                        return anyhow::Ok(());
+                    }
+                    if in_data {
+                        if line == b".\r\n" {
+                            in_data = false;
+                            writer
+                                .write_all(b"250 2.0.0 Ok: queued as 4B1C2D\r\n")
+                                .await?;
+                        }
+                        continue;
+                    }
+                    let command = String::from_utf8_lossy(&line).to_uppercase();
+                    let reply: &[u8] = if command.starts_with("DATA")
+                        && messages.fetch_add(1, Ordering::SeqCst) == 0
+                    {
+                        in_data = true;
+                        b"354 go ahead\r\n"
+                    } else if command.starts_with("DATA") {
+                        b"554 5.7.1 rejected\r\n"
+                    } else if command.starts_with("QUIT") {
+                        writer.write_all(b"221 bye\r\n").await?;
+                        return Ok(());
+                    } else {
+                        b"250 ok\r\n"
+                    };
+                    writer.write_all(reply).await?;
+                }
+            });
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_archive_keeps_receipts_of_sent_parts() -> anyhow::Result<()> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let port = listener.local_addr()?.port();
+        let relay = tokio::spawn(relay_rejecting_second(listener));
+
+        let sender = SendGridSender::new(&SendGridConfig {
+            api_key: String::new(),
+            protocol: SendGridProtocol::Smtp,
+            from_address: "me@example.com".to_string(),
+            max_message_size: crate::email::config::MAX_MESSAGE_SIZE,
+            relay: Some(crate::email::smtp::SmtpConfig {
+                host: "127.0.0.1".to_string(),
+                port,
+                username: None,
+                tls: crate::email::smtp::SmtpTls::None,
+            }),
+            smime: None,
+            dkim: None,
+            reply_to: None,
+            headers: Headers::new(),
+            options: SendGridOptions::default(),
+            sandbox: false,
+        })?;
+        let temp_dir = tempfile::tempdir()?;
+        let parts: Vec<String> = ["report.zip.001", "report.zip.002"]
+            .iter()
+            .map(|name| {
+                let path = temp_dir.path().join(name);
+                std::fs::write(&path, b"part")?;
+                Ok(path.to_string_lossy().into_owned())
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        let mut receipts = Vec::new();
+        let result = sender
+            .send_archive(
+                &["bob@example.net".to_string()],
+                &[],
+                "Report",
+                "Attached.",
+                &parts,
+                &Headers::new(),
+                &mut receipts,
+            )
+            .await;
+        assert!(result.is_err());
+        assert_eq!(receipts.len(), 1);
+        assert_eq!(receipts[0].provider_message_id.as_deref(), Some("4B1C2D"));
+        drop(sender);
+        relay.abort();
+        Ok(())
+    }
+}
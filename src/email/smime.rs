@@ -0,0 +1,211 @@
+use crate::pubkey::{self, Keyring};
+use anyhow::Context;
+use openssl::pkcs12::Pkcs12;
+use openssl::pkcs7::{Pkcs7, Pkcs7Flags};
+use openssl::pkey::{PKey, Private};
+use openssl::stack::Stack;
+use openssl::symm::Cipher;
+use openssl::x509::X509;
+use serde::Deserialize;
+use std::path::PathBuf;
+
+/// `[profiles.NAME.smime]`: sign outgoing email with a local certificate.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SmimeConfig {
+    /// `.p12` or `.pfx` file with the sender's certificate and private key.
+    pub pkcs12: PathBuf,
+    /// Also encrypt every email to the recipients' certificates.
+    #[serde(default)]
+    pub encrypt: bool,
+}
+
+/// The sender's identity, loaded from the PKCS#12 file.
+pub struct Smime {
+    cert: X509,
+    key: PKey<Private>,
+    chain: Stack<X509>,
+    encrypt: bool,
+    keyring: Keyring,
+}
+
+impl Smime {
+    /// Reads the PKCS#12 file, unlocked with `PPAP_SMIME_PASSWORD` (empty if unset), and the
+    /// recipients' certificates from `keys.toml`.
+    pub fn load(config: &SmimeConfig) -> anyhow::Result<Self> {
+        let path = &config.pkcs12;
+        let der = std::fs::read(path)
+            .with_context(|| format!("Failed to read S/MIME certificate: {}", path.display()))?;
+        let password = std::env::var("PPAP_SMIME_PASSWORD").unwrap_or_default();
+        let parsed = Pkcs12::from_der(&der)
+            .and_then(|pkcs12| pkcs12.parse2(&password))
+            .with_context(|| {
+                format!(
+                    "Failed to open {} (is PPAP_SMIME_PASSWORD set?)",
+                    path.display()
+                )
+            })?;
+        let (Some(cert), Some(key)) = (parsed.cert, parsed.pkey) else {
+            return Err(anyhow::anyhow!(
+                "{} does not contain a certificate and private key",
+                path.display()
+            ));
+        };
+        let chain = match parsed.ca {
+            Some(chain) => chain,
+            None => Stack::new()?,
+        };
+        let keyring = if config.encrypt {
+            pubkey::load(&pubkey::keyring_file_path())?
+        } else {
+            Keyring::new()
+        };
+        Ok(Self {
+            cert,
+            key,
+            chain,
+            encrypt: config.encrypt,
+            keyring,
+        })
+    }
+
+    pub fn encrypts(&self) -> bool {
+        self.encrypt
+    }
+
+    /// Certificates from the address book, used before those in `keys.toml`.
+    pub fn add_certificates(&mut self, keys: &Keyring) {
+        for (email, keys) in keys {
+            if !keys.smime.is_empty() {
+                self.keyring.insert(email.clone(), keys.clone());
+            }
+        }
+    }
+
+    /// Signs a MIME entity (its headers and body) and, if configured, encrypts the result to
+    /// the certificates of `recipients` and the sender. Returns the new entity.
+    pub fn wrap(&self, entity: &[u8], recipients: &[String]) -> anyhow::Result<Vec<u8>> {
+        // BINARY keeps the CRLF entity byte for byte, so the signature matches what is sent.
+        let flags = Pkcs7Flags::DETACHED | Pkcs7Flags::BINARY;
+        let signed = Pkcs7::sign(&self.cert, &self.key, &self.chain, entity, flags)
+            .context("Failed to sign the email")?
+            .to_smime(entity, flags | Pkcs7Flags::CRLFEOL)?;
+        let signed = without_mime_version(signed);
+        if !self.encrypt {
+            return Ok(signed);
+        }
+
+        let mut certs = Stack::new()?;
+        certs.push(self.cert.clone())?;
+        for path in pubkey::certificates(recipients, &[&self.keyring])? {
+            let pem = std::fs::read(&path)
+                .with_context(|| format!("Failed to read certificate: {}", path.display()))?;
+            let cert = X509::from_pem(&pem)
+                .with_context(|| format!("Invalid certificate: {}", path.display()))?;
+            certs.push(cert)?;
+        }
+        let encrypted = Pkcs7::encrypt(&certs, &signed, Cipher::aes_256_cbc(), Pkcs7Flags::BINARY)
+            .context("Failed to encrypt the email")?
+            .to_smime(&[], Pkcs7Flags::BINARY | Pkcs7Flags::CRLFEOL)?;
+        Ok(without_mime_version(encrypted))
+    }
+}
+
+/// openssl starts every entity with `MIME-Version`, which belongs to the message headers.
+fn without_mime_version(entity: Vec<u8>) -> Vec<u8> {
+    match entity.strip_prefix(b"MIME-Version: 1.0\r\n") {
+        Some(rest) => rest.to_vec(),
+        None => entity,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pubkey::PublicKeys;
+    use openssl::asn1::Asn1Time;
+    use openssl::hash::MessageDigest;
+    use openssl::rsa::Rsa;
+    use openssl::x509::{X509Builder, X509NameBuilder};
+    use tempfile::tempdir;
+
+    /// A self-signed certificate and its key.
+    fn identity(email: &str) -> (X509, PKey<Private>) {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", email).unwrap();
+        let name = name.build();
+        let mut builder = X509Builder::new().unwrap();
+        builder.set_version(2).unwrap();
+        let serial = openssl::bn::BigNum::from_u32(rand::random::<u32>() >> 1).unwrap();
+        builder
+            .set_serial_number(&serial.to_asn1_integer().unwrap())
+            .unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        builder.sign(&key, MessageDigest::sha256()).unwrap();
+        (builder.build(), key)
+    }
+
+    #[test]
+    fn test_sign_and_encrypt() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let (cert, key) = identity("me@example.com");
+        let pkcs12 = Pkcs12::builder()
+            .name("me")
+            .pkey(&key)
+            .cert(&cert)
+            .build2("")?;
+        let pkcs12_path = temp_dir.path().join("me.p12");
+        std::fs::write(&pkcs12_path, pkcs12.to_der()?)?;
+        let (bob_cert, bob_key) = identity("bob@example.com");
+        let bob_pem = temp_dir.path().join("bob.pem");
+        std::fs::write(&bob_pem, bob_cert.to_pem()?)?;
+
+        let mut smime = Smime::load(&SmimeConfig {
+            pkcs12: pkcs12_path,
+            encrypt: false,
+        })?;
+        let entity = b"Content-Type: text/plain; charset=utf-8\r\n\r\nThe password is 1234\r\n";
+        let signed = smime.wrap(entity, &[])?;
+        assert!(signed.starts_with(b"Content-Type: multipart/signed"));
+        let (pkcs7, content) = Pkcs7::from_smime(&signed)?;
+        let mut store = openssl::x509::store::X509StoreBuilder::new()?;
+        store.add_cert(cert.clone())?;
+        let no_certs: Stack<X509> = Stack::new()?;
+        let mut verified = Vec::new();
+        pkcs7.verify(
+            &no_certs,
+            &store.build(),
+            content.as_deref(),
+            Some(&mut verified),
+            Pkcs7Flags::BINARY,
+        )?;
+        assert_eq!(verified, entity);
+
+        smime.encrypt = true;
+        let recipients = vec!["Bob <bob@example.com>".to_string()];
+        assert!(smime.wrap(entity, &recipients).is_err());
+        smime.add_certificates(&Keyring::from([(
+            "bob@example.com".to_string(),
+            PublicKeys {
+                smime: vec![bob_pem],
+                ..Default::default()
+            },
+        )]));
+        let encrypted = smime.wrap(entity, &recipients)?;
+        assert!(String::from_utf8(encrypted.clone())?.contains("smime-type=enveloped-data"));
+        let (pkcs7, _) = Pkcs7::from_smime(&encrypted)?;
+        let decrypted = pkcs7.decrypt(&bob_key, &bob_cert, Pkcs7Flags::BINARY)?;
+        assert!(decrypted.starts_with(b"Content-Type: multipart/signed"));
+        assert!(String::from_utf8(decrypted)?.contains("The password is 1234"));
+        Ok(())
+    }
+}
@@ -0,0 +1,179 @@
+use anyhow::Context;
+use lettre::address::Envelope;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+use serde::Deserialize;
+use std::time::Duration;
+
+const SENDGRID_SMTP_HOST: &str = "smtp.sendgrid.net";
+
+/// `[profiles.NAME.smtp]`: a relay of your own instead of SendGrid's.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SmtpConfig {
+    pub host: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    /// The password is read from `PPAP_SMTP_PASSWORD`. No login without a user name.
+    pub username: Option<String>,
+    #[serde(default)]
+    pub tls: SmtpTls,
+}
+
+fn default_port() -> u16 {
+    587
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    #[default]
+    Starttls,
+    /// TLS from the first byte, usually on port 465.
+    Tls,
+    /// Plain text, only for a relay on the same host or network.
+    None,
+}
+
+/// An SMTP relay, which takes messages as raw MIME.
+pub struct SmtpRelay {
+    host: String,
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl SmtpRelay {
+    /// SendGrid accepts the literal user name `apikey` with the API key as password.
+    pub fn sendgrid(api_key: &str) -> anyhow::Result<Self> {
+        let transport = AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(SENDGRID_SMTP_HOST)?
+            .port(587)
+            .credentials(Credentials::new("apikey".to_string(), api_key.to_string()))
+            .timeout(Some(Duration::from_secs(60)))
+            .build();
+        Ok(Self {
+            host: SENDGRID_SMTP_HOST.to_string(),
+            transport,
+        })
+    }
+
+    pub fn new(config: &SmtpConfig) -> anyhow::Result<Self> {
+        let mut builder = match config.tls {
+            SmtpTls::Starttls => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)?
+            }
+            SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)?,
+            SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host),
+        }
+        .port(config.port)
+        .timeout(Some(Duration::from_secs(60)));
+        if let Some(username) = &config.username {
+            let password = std::env::var("PPAP_SMTP_PASSWORD").unwrap_or_default();
+            builder = builder.credentials(Credentials::new(username.clone(), password));
+        }
+        Ok(Self {
+            host: format!("{}:{}", config.host, config.port),
+            transport: builder.build(),
+        })
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// Returns the queue ID from the relay's reply, when it gives one.
+    pub async fn send(
+        &self,
+        envelope: &Envelope,
+        message: &[u8],
+    ) -> anyhow::Result<Option<String>> {
+        let response = self
+            .transport
+            .send_raw(envelope, message)
+            .await
+            .with_context(|| format!("Failed to send email via {}", self.host))?;
+        let queue_id = response.message().find_map(queue_id);
+        Ok(queue_id)
+    }
+}
+
+/// `Ok: queued as 4B1C2D` is how Postfix and SendGrid name the queued message.
+fn queue_id(line: &str) -> Option<String> {
+    let (_, id) = line.split_once("queued as ")?;
+    let id = id.split_whitespace().next()?;
+    Some(id.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    /// Accepts one message the way a local relay would and returns its DATA.
+    async fn stand_in_relay(listener: TcpListener) -> anyhow::Result<Vec<u8>> {
+        let (stream, _) = listener.accept().await?;
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+        writer.write_all(b"220 localhost ESMTP\r\n").await?;
+        let mut data = Vec::new();
+        let mut in_data = false;
+        loop {
+            let mut line = Vec::new();
+            if reader.read_until(b'\n', &mut line).await? == 0 {
+                return Ok(data);
+            }
+            if in_data {
+                if line == b".\r\n" {
+                    // The CRLF before the final dot belongs to the terminator.
+                    data.truncate(data.len().saturating_sub(2));
+                    in_data = false;
+                    writer
+                        .write_all(b"250 2.0.0 Ok: queued as 4B1C2D\r\n")
+                        .await?;
+                } else {
+                    data.extend_from_slice(&line);
+                }
+                continue;
+            }
+            let command = String::from_utf8_lossy(&line).to_uppercase();
+            let reply: &[u8] = if command.starts_with("EHLO") {
+                b"250 localhost\r\n"
+            } else if command.starts_with("DATA") {
+                in_data = true;
+                b"354 go ahead\r\n"
+            } else if command.starts_with("QUIT") {
+                writer.write_all(b"221 bye\r\n").await?;
+                return Ok(data);
+            } else {
+                b"250 ok\r\n"
+            };
+            writer.write_all(reply).await?;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_to_own_relay() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let port = listener.local_addr()?.port();
+        let server = tokio::spawn(stand_in_relay(listener));
+
+        let relay = SmtpRelay::new(&SmtpConfig {
+            host: "127.0.0.1".to_string(),
+            port,
+            username: None,
+            tls: SmtpTls::None,
+        })?;
+        let envelope = Envelope::new(
+            Some("me@example.com".parse()?),
+            vec!["bob@example.net".parse()?],
+        )?;
+        let message = b"From: me@example.com\r\nTo: bob@example.net\r\n\r\nHello\r\n";
+        assert_eq!(
+            relay.send(&envelope, message).await?.as_deref(),
+            Some("4B1C2D")
+        );
+        drop(relay);
+
+        assert_eq!(server.await??, message);
+        Ok(())
+    }
+}
@@ -0,0 +1,111 @@
+use std::process::ExitCode;
+
+/// Failure categories, each with its own process exit code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureKind {
+    Config,
+    Input,
+    Archive,
+    Delivery,
+    /// The archive was delivered but the password email was not.
+    PasswordDelivery,
+    /// Blocked by the send policy before anything was sent.
+    Policy,
+}
+
+impl FailureKind {
+    pub fn exit_code(self) -> u8 {
+        match self {
+            FailureKind::Config => 3,
+            FailureKind::Input => 4,
+            FailureKind::Archive => 5,
+            FailureKind::Delivery => 6,
+            FailureKind::PasswordDelivery => 7,
+            FailureKind::Policy => 8,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            FailureKind::Config => "config",
+            FailureKind::Input => "input",
+            FailureKind::Archive => "archive",
+            FailureKind::Delivery => "delivery",
+            FailureKind::PasswordDelivery => "password_delivery",
+            FailureKind::Policy => "policy",
+        }
+    }
+}
+
+/// Wraps an error with its category. Displays exactly like the wrapped error.
+#[derive(Debug)]
+pub struct Failure {
+    pub kind: FailureKind,
+    error: anyhow::Error,
+}
+
+impl std::fmt::Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(&self.error, f)
+    }
+}
+
+impl std::error::Error for Failure {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.error.source()
+    }
+}
+
+pub trait ResultExt<T> {
+    fn categorize(self, kind: FailureKind) -> anyhow::Result<T>;
+}
+
+impl<T, E: Into<anyhow::Error>> ResultExt<T> for Result<T, E> {
+    /// Tags the error with `kind` unless it already carries a category.
+    fn categorize(self, kind: FailureKind) -> anyhow::Result<T> {
+        self.map_err(|e| {
+            let error: anyhow::Error = e.into();
+            if error.downcast_ref::<Failure>().is_some() {
+                error
+            } else {
+                Failure { kind, error }.into()
+            }
+        })
+    }
+}
+
+pub fn kind_of(error: &anyhow::Error) -> Option<FailureKind> {
+    error.downcast_ref::<Failure>().map(|f| f.kind)
+}
+
+/// 1 for errors without a category, matching the previous behavior.
+pub fn exit_code(error: &anyhow::Error) -> ExitCode {
+    ExitCode::from(kind_of(error).map_or(1, FailureKind::exit_code))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_categorize_keeps_message() {
+        let result: anyhow::Result<()> =
+            Err(anyhow::anyhow!("SendGrid API key is not set")).categorize(FailureKind::Config);
+        let error = result.unwrap_err();
+        assert_eq!(error.to_string(), "SendGrid API key is not set");
+        assert_eq!(kind_of(&error), Some(FailureKind::Config));
+    }
+
+    #[test]
+    fn test_first_category_wins() {
+        let result: anyhow::Result<()> = Err(anyhow::anyhow!("boom"))
+            .categorize(FailureKind::Input)
+            .categorize(FailureKind::Delivery);
+        assert_eq!(kind_of(&result.unwrap_err()), Some(FailureKind::Input));
+    }
+
+    #[test]
+    fn test_uncategorized_error() {
+        assert_eq!(kind_of(&anyhow::anyhow!("boom")), None);
+    }
+}
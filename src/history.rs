@@ -0,0 +1,277 @@
+use crate::audit;
+use crate::email::sender::Receipt;
+use anyhow::Context;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Delivered,
+    /// The archive, or some of its parts, was delivered but the password email was not.
+    PasswordFailed,
+    Failed,
+}
+
+impl std::fmt::Display for Outcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Outcome::Delivered => write!(f, "delivered"),
+            Outcome::PasswordFailed => write!(f, "password-failed"),
+            Outcome::Failed => write!(f, "failed"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileDigest {
+    pub name: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+impl FileDigest {
+    pub fn from_path(path: &str) -> anyhow::Result<Self> {
+        let content =
+            std::fs::read(path).with_context(|| format!("Failed to read file: {}", path))?;
+        let name = Path::new(path)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or(path)
+            .to_string();
+        Ok(Self {
+            name,
+            size: content.len() as u64,
+            sha256: hex::encode(Sha256::digest(&content)),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransferRecord {
+    pub timestamp: DateTime<Utc>,
+    pub recipients: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cc: Vec<String>,
+    /// Where the password went when it was not sent to `recipients`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub password_recipients: Vec<String>,
+    pub files: Vec<FileDigest>,
+    pub archive_size: u64,
+    #[serde(default)]
+    pub message_ids: Vec<String>,
+    /// Code shown in the subject of both emails.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub reference: String,
+    /// The provider's answer for each email.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub receipts: Vec<Receipt>,
+    pub secure: bool,
+    pub outcome: Outcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct Filter {
+    pub recipient: Option<String>,
+    pub since: Option<NaiveDate>,
+    pub until: Option<NaiveDate>,
+}
+
+impl Filter {
+    pub fn matches(&self, record: &TransferRecord) -> bool {
+        if let Some(recipient) = &self.recipient {
+            let needle = recipient.to_lowercase();
+            if !record
+                .recipients
+                .iter()
+                .any(|r| r.to_lowercase().contains(&needle))
+            {
+                return false;
+            }
+        }
+        let date = record.timestamp.date_naive();
+        if self.since.is_some_and(|since| date < since) {
+            return false;
+        }
+        if self.until.is_some_and(|until| date > until) {
+            return false;
+        }
+        true
+    }
+}
+
+pub fn history_file_path() -> PathBuf {
+    std::env::var_os("PPAP_HISTORY")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| crate::config::data_dir().join("history.jsonl"))
+}
+
+/// A [`TransferRecord`] sealed into the hash chain, see [`crate::audit`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogEntry {
+    #[serde(flatten)]
+    pub record: TransferRecord,
+    pub prev_hash: String,
+    pub hash: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hmac: Option<String>,
+}
+
+pub fn append(path: &Path, record: &TransferRecord) -> anyhow::Result<()> {
+    let key = audit::load_key(&audit::key_path())?;
+    append_with_key(path, record, key.as_deref())
+}
+
+pub fn append_with_key(
+    path: &Path,
+    record: &TransferRecord,
+    key: Option<&[u8]>,
+) -> anyhow::Result<()> {
+    let mut entries = read_entries(path)?;
+    let head = audit::read_head(path)?;
+    let keyed_from = key.map(|_| audit::Head::keyed_from(head.as_ref(), &entries));
+    let prev_hash = entries
+        .last()
+        .map(|entry| entry.hash.clone())
+        .unwrap_or_else(|| audit::GENESIS_HASH.to_string());
+    let hash = audit::record_hash(&prev_hash, record)?;
+    let hmac = key.map(|key| audit::record_hmac(key, &hash));
+    let entry = LogEntry {
+        record: record.clone(),
+        prev_hash,
+        hash,
+        hmac,
+    };
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open history: {}", path.display()))?;
+    writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+
+    entries.push(entry);
+    audit::write_head(path, &audit::Head::new(&entries, keyed_from, key))
+}
+
+/// Authenticates the head with a new audit key; records from now on must carry an HMAC.
+pub fn seal(path: &Path, key: &[u8]) -> anyhow::Result<()> {
+    let entries = read_entries(path)?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    audit::write_head(
+        path,
+        &audit::Head::new(&entries, Some(entries.len()), Some(key)),
+    )
+}
+
+pub fn read_entries(path: &Path) -> anyhow::Result<Vec<LogEntry>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let file = std::fs::File::open(path)
+        .with_context(|| format!("Failed to open history: {}", path.display()))?;
+    std::io::BufReader::new(file)
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.as_ref().is_ok_and(|l| l.trim().is_empty()))
+        .map(|(i, line)| {
+            serde_json::from_str(&line?)
+                .with_context(|| format!("Invalid history record at line {}", i + 1))
+        })
+        .collect()
+}
+
+pub fn read(path: &Path) -> anyhow::Result<Vec<TransferRecord>> {
+    Ok(read_entries(path)?
+        .into_iter()
+        .map(|entry| entry.record)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn record(recipient: &str, timestamp: &str) -> TransferRecord {
+        TransferRecord {
+            timestamp: timestamp.parse().unwrap(),
+            recipients: vec![recipient.to_string()],
+            files: vec![FileDigest {
+                name: "report.pdf".to_string(),
+                size: 3,
+                sha256: "abc".to_string(),
+            }],
+            cc: vec![],
+            password_recipients: vec![],
+            archive_size: 10,
+            message_ids: vec![],
+            reference: String::new(),
+            receipts: vec![],
+            secure: false,
+            outcome: Outcome::Delivered,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn test_append_and_read() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("history.jsonl");
+
+        append_with_key(
+            &path,
+            &record("a@example.com", "2026-01-01T00:00:00Z"),
+            None,
+        )?;
+        append_with_key(
+            &path,
+            &record("b@example.com", "2026-01-02T00:00:00Z"),
+            None,
+        )?;
+
+        let records = read(&path)?;
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].recipients, vec!["b@example.com"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter() {
+        let filter = Filter {
+            recipient: Some("EXAMPLE.jp".to_string()),
+            since: NaiveDate::from_ymd_opt(2026, 1, 2),
+            until: NaiveDate::from_ymd_opt(2026, 1, 31),
+        };
+        assert!(filter.matches(&record("a@example.jp", "2026-01-02T23:59:59Z")));
+        assert!(!filter.matches(&record("a@example.com", "2026-01-10T00:00:00Z")));
+        assert!(!filter.matches(&record("a@example.jp", "2026-01-01T12:00:00Z")));
+        assert!(!filter.matches(&record("a@example.jp", "2026-02-01T00:00:00Z")));
+    }
+
+    #[test]
+    fn test_file_digest() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("hello.txt");
+        std::fs::write(&path, b"hello")?;
+
+        let digest = FileDigest::from_path(path.to_str().unwrap())?;
+        assert_eq!(digest.name, "hello.txt");
+        assert_eq!(digest.size, 5);
+        assert_eq!(
+            digest.sha256,
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+        Ok(())
+    }
+}
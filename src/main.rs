@@ -1,149 +1,681 @@
+mod address;
+mod audit;
+mod batch;
+mod cli;
+mod config;
+mod contacts;
+mod credentials;
+mod dlp;
 mod email;
+mod error;
+mod history;
+mod malware;
+mod output;
+mod policy;
+mod pubkey;
+mod sanitize;
+mod schedule;
+mod serve;
+mod storage;
+mod template;
+mod transfer;
+mod wizard;
 mod zip;
 
 use anyhow::Result;
 use clap::Parser;
-use email::{config::SendGridConfig, sender::EmailSender, sendgrid::SendGridRestSender};
-use std::io::Write;
-
-#[derive(Parser, Debug)]
-#[command(name = "ppap")]
-#[command(author = "Rindrics")]
-#[command(version = "0.1.0")]
-#[command(about = "CLI tool that uses traditional Japanese file sharing protocol")]
-struct Opts {
-    #[arg(help = "Target file to be sent")]
-    file: String,
-
-    #[arg(help = "Email address of the recipient")]
-    email: String,
-
-    #[arg(
-        short = 's',
-        long = "secure",
-        help = "Enhance security by sending an incorrect password (Good luck explaining this to your recipient)"
-    )]
-    secure: bool,
-
-    #[arg(
-        short = 'a',
-        long = "after",
-        value_name = "HOURS",
-        help = "Delay password delivery by specified hours (Because waiting makes everything more secure)"
-    )]
-    after: Option<u64>,
-}
+use cli::{
+    AuditAction, BatchArgs, Command, ConfigAction, HistoryArgs, Opts, ResumeArgs, ScheduleAction,
+    SendArgs, ServeArgs, UnzipArgs,
+};
+use config::Settings;
+use email::{config::SendGridConfig, sender::EmailSender, sendgrid::SendGridSender};
+use error::{FailureKind, ResultExt};
+use std::io::IsTerminal;
+use std::process::ExitCode;
+use transfer::Transfer;
 
 async fn async_main() -> Result<()> {
-    // Show help if no arguments provided
-    if std::env::args().count() == 1 {
-        println!("{}", get_help_text());
-        return Ok(());
+    // Without arguments an interactive terminal gets the wizard, anything else the help.
+    if std::env::args_os().len() == 1 && std::io::stdin().is_terminal() {
+        let settings = Settings::load(None).categorize(FailureKind::Config)?;
+        return run_wizard(&settings).await;
     }
-
     let opts: Opts = Opts::parse();
+    let profile = opts.profile.clone();
+    let command = opts.into_command();
+    if let Command::Send(args) = &command {
+        output::set_format(args.output);
+    }
+    let settings = Settings::load(profile).categorize(FailureKind::Config)?;
 
-    // Step 1: Compress file and get password
-    println!("Compressing file: {}", opts.file);
-    let (zip_path, password) = zip::compress_file(&opts.file)?;
-    println!("File compressed successfully: {}", zip_path);
-
-    // Step 2: Initialize SendGrid
-    let config = SendGridConfig::from_env()?;
-    let sender = SendGridRestSender::new(&config);
-
-    // Step 3: Send email #1 with ZIP attachment
-    println!("\nSending email #1: ZIP file attachment");
-    sender.send_email_with_attachment(
-        &opts.email,
-        "Secure File Transfer - Encrypted Document",
-        "Please find the attached password-protected file.\n\nThe password will be sent in a separate email for security purposes.",
-        &zip_path,
-    ).await?;
-    println!("Email #1 sent successfully!");
-
-    // Step 4: Determine which password to send
-    let password_to_send = if opts.secure {
-        println!("\n[SECURE MODE ACTIVATED]");
-        println!("Generating incorrect password for maximum security...");
-        get_password_to_send(&password, true)
-    } else {
-        password.clone()
-    };
+    match command {
+        Command::Send(args) => send(&settings, args).await,
+        Command::Batch(args) => send_batch(&settings, args).await,
+        Command::Unzip(args) => unzip(args),
+        Command::Serve(args) => serve_downloads(&settings, args).await,
+        Command::History(args) => show_history(args),
+        Command::Schedule { action } => match action {
+            ScheduleAction::List => list_scheduled(),
+            ScheduleAction::Cancel { id } => cancel_scheduled(&id),
+        },
+        Command::Resume(args) => resume(args).await,
+        Command::Audit { action } => match action {
+            AuditAction::Verify => verify_audit_log(),
+            AuditAction::InitKey => init_audit_key(),
+        },
+        Command::Config { action } => match action {
+            ConfigAction::Show => show_config(&settings),
+            ConfigAction::SetKey { stdin } => set_key(&settings, stdin),
+        },
+    }
+}
 
-    // Step 5: Handle delayed sending if requested
-    if let Some(hours) = opts.after {
-        println!("\nPassword email will be sent in {} hours.", hours);
-        println!("Please keep this terminal open. Press Ctrl+C to cancel.");
-        println!("(File email has already been sent)");
-
-        let total_seconds = hours * 3600;
-        for elapsed in (0..total_seconds).step_by(60) {
-            let remaining = total_seconds - elapsed;
-            print!(
-                "\rWaiting... ({:02}:{:02}:{:02} remaining)  ",
-                remaining / 3600,
-                (remaining % 3600) / 60,
-                remaining % 60
-            );
-            let _ = std::io::stdout().flush();
-            tokio::time::sleep(std::time::Duration::from_secs(60)).await;
-        }
-        println!("\n\nDelay complete! Sending password email now...");
+/// The profile with `--from-name`, `--reply-to`, `--header` and `--sandbox` on top.
+fn with_sender_options(
+    settings: &Settings,
+    from_name: Option<String>,
+    reply_to: Option<String>,
+    headers: Vec<(String, String)>,
+    sandbox: bool,
+) -> Settings {
+    let mut settings = settings.clone();
+    let profile = &mut settings.profile;
+    if from_name.is_some() {
+        profile.from_name = from_name;
+    }
+    if reply_to.is_some() {
+        profile.reply_to = reply_to;
     }
+    profile.headers.extend(headers);
+    if sandbox {
+        profile.sandbox = Some(true);
+    }
+    settings
+}
 
-    // Step 6: Send email #2 with password
-    println!("\nSending email #2: Password");
-    let password_email_body = format!(
-        "Password for the encrypted file: {}\n\nPlease use this password to open the ZIP file sent in the previous email.",
-        password_to_send
+async fn send(settings: &Settings, opts: SendArgs) -> Result<()> {
+    let settings = &with_sender_options(
+        settings,
+        opts.from_name,
+        opts.reply_to,
+        opts.headers,
+        opts.sandbox,
     );
+    let mut transfer = Transfer {
+        files: vec![opts.file],
+        recipients: Vec::new(),
+        cc: Vec::new(),
+        password_recipients: Vec::new(),
+        secure: opts.secure,
+        after: opts.after.or(settings.profile.delay_hours),
+        templates: settings.profile.templates(),
+        format: Default::default(),
+        assume_yes: opts.yes,
+        strip_metadata: opts.strip_metadata || settings.profile.strip_metadata.unwrap_or(false),
+        max_message_mb: None,
+        public_keys: Default::default(),
+        delivery: opts
+            .delivery
+            .or(settings.profile.delivery)
+            .unwrap_or_default(),
+    };
+    match contacts::alias(&opts.email) {
+        Some(alias) => {
+            let book =
+                contacts::load(&contacts::contacts_file_path()).categorize(FailureKind::Config)?;
+            contacts::lookup(&book, alias)
+                .and_then(|contact| contact.apply(&mut transfer, settings))
+                .categorize(FailureKind::Config)?;
+        }
+        None => transfer.recipients.push(opts.email),
+    }
+    if let Some(encryption) = opts.encryption {
+        transfer.format.encryption = encryption;
+    }
+    transfer
+        .normalize_addresses()
+        .categorize(FailureKind::Input)?;
+    let report = transfer::run(settings, &transfer).await?;
+    print_report(&transfer, &report)
+}
 
-    sender
-        .send_email(
-            &opts.email,
-            "Secure File Transfer - Password",
-            &password_email_body,
-        )
-        .await?;
-    println!("Email #2 sent successfully!");
+async fn run_wizard(settings: &Settings) -> Result<()> {
+    let Some(mut transfer) = wizard::run(settings).categorize(FailureKind::Input)? else {
+        println!("Cancelled, nothing was sent.");
+        return Ok(());
+    };
+    transfer
+        .normalize_addresses()
+        .categorize(FailureKind::Input)?;
+    let report = transfer::run(settings, &transfer).await?;
+    print_report(&transfer, &report)
+}
 
-    // Step 7: Cleanup
-    println!("\nCleaning up temporary files...");
-    zip::cleanup_temp_file(&zip_path)?;
+fn print_report(transfer: &Transfer, report: &transfer::Report) -> Result<()> {
+    let public_key = transfer.format.encryption.is_public_key();
+    if output::is_json() {
+        let result = serde_json::json!({
+            "status": "ok",
+            "archive": {
+                "path": report.archive_path,
+                "size": report.archive.size,
+                "sha256": report.archive.sha256,
+                "parts": report.archive_parts,
+            },
+            "link": report.link.as_ref().map(|link| serde_json::json!({
+                "url": link.url,
+                "expires_at": link.expires_at,
+            })),
+            "files": report.files,
+            "recipients": transfer.recipients,
+            "cc": transfer.cc,
+            "password_recipients": if public_key { &[] } else { transfer.password_addresses().0 },
+            "message_ids": report.message_ids,
+            "receipts": report.receipts,
+            "reference": report.reference,
+            "sandbox": report.sandbox,
+            "metadata_removed": report.metadata_removed,
+            "password_scheduled_at": report.password_scheduled_at,
+            "secure": transfer.secure,
+        });
+        println!("{}", serde_json::to_string_pretty(&result)?);
+        return Ok(());
+    }
 
     println!("\n=== PPAP Protocol Complete ===");
-    println!("File sent successfully to: {}", opts.email);
-    if opts.secure {
+    if report.sandbox {
+        println!("Sandbox mode: SendGrid accepted every email as valid, nothing was delivered");
+    }
+    println!(
+        "File sent successfully to: {}",
+        transfer.recipients.join(", ")
+    );
+    println!("Reference: {}", report.reference);
+    for receipt in &report.receipts {
+        println!(
+            "Accepted by {} at {}: {}",
+            receipt.provider,
+            receipt.accepted_at.format("%Y-%m-%d %H:%M:%S UTC"),
+            receipt
+                .provider_message_id
+                .as_deref()
+                .unwrap_or(&receipt.message_id)
+        );
+    }
+    if let Some(link) = &report.link {
+        println!("Download link (expires {}): {}", link.expires_at, link.url);
+    }
+    if report.archive_parts.len() > 1 {
+        println!(
+            "Archive was split into {} emails",
+            report.archive_parts.len()
+        );
+    }
+    if public_key {
+        println!("Encrypted to the recipients' public keys, no password was sent");
+    } else if !transfer.password_recipients.is_empty() {
+        println!(
+            "Password sent to: {}",
+            transfer.password_recipients.join(", ")
+        );
+    }
+    if transfer.secure && !public_key {
         println!(
             "[WARNING] Secure mode was enabled. The recipient received an INCORRECT password."
         );
-        println!("Real password (for your records): {}", password);
+        println!("Real password (for your records): {}", report.password);
+    }
+
+    Ok(())
+}
+
+async fn send_batch(settings: &Settings, args: BatchArgs) -> Result<()> {
+    let settings = &with_sender_options(
+        settings,
+        args.from_name,
+        args.reply_to,
+        args.headers,
+        args.sandbox,
+    );
+    let manifest = std::path::Path::new(&args.manifest);
+    let rows = batch::read_manifest(manifest)?;
+    println!("Loaded {} row(s) from {}", rows.len(), manifest.display());
+
+    let send_policy = policy::load(&policy::policy_file_path()).categorize(FailureKind::Config)?;
+    let config = SendGridConfig::from_env(settings)?;
+    let sender = SendGridSender::new(&config).categorize(FailureKind::Config)?;
+    let results = batch::run(
+        rows,
+        &sender,
+        &settings.profile.templates(),
+        &settings.profile.password_policy(),
+        &send_policy,
+        args.yes,
+        args.concurrency,
+    )
+    .await;
+
+    let results_path = args
+        .results
+        .map(std::path::PathBuf::from)
+        .unwrap_or_else(|| batch::default_results_path(manifest));
+    batch::write_results(&results_path, &results)?;
+
+    let failed = results
+        .iter()
+        .filter(|r| r.status != history::Outcome::Delivered)
+        .count();
+    println!("\n=== Batch Complete ===");
+    if config.sandbox {
+        println!("Sandbox mode: SendGrid only validated the emails, nothing was delivered");
+    }
+    println!(
+        "{} row(s) sent, {} failed. Results: {}",
+        results.len() - failed,
+        failed,
+        results_path.display()
+    );
+    if failed > 0 {
+        return Err(anyhow::anyhow!("{} row(s) failed", failed));
+    }
+    Ok(())
+}
+
+fn unzip(args: UnzipArgs) -> Result<()> {
+    let password = match args.password {
+        Some(password) => password,
+        None => dialoguer::Password::new()
+            .with_prompt("Password")
+            .interact()?,
+    };
+
+    println!("Extracting: {}", args.archive);
+    let extracted = zip::extract_archive(&args.archive, &password, &args.output)?;
+    for path in &extracted {
+        println!("  {}", path.display());
+    }
+    println!("Extracted {} file(s) to {}", extracted.len(), args.output);
+    Ok(())
+}
+
+async fn serve_downloads(settings: &Settings, args: ServeArgs) -> Result<()> {
+    let mut config = settings
+        .profile
+        .serve
+        .clone()
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "ppap serve needs a [profiles.NAME.serve] section in {}",
+                settings.config_path.display()
+            )
+        })
+        .categorize(FailureKind::Config)?;
+    if args.listen.is_some() {
+        config.listen = args.listen;
+    }
+    serve::run(&config, &serve::shares_dir()).await
+}
+
+fn show_history(args: HistoryArgs) -> Result<()> {
+    let filter = history::Filter {
+        recipient: args.recipient,
+        since: args.since,
+        until: args.until,
+    };
+    let records = history::read(&history::history_file_path())?;
+
+    for record in records.iter().filter(|r| filter.matches(r)) {
+        let files: Vec<String> = record
+            .files
+            .iter()
+            .map(|f| {
+                format!(
+                    "{} ({} bytes, sha256:{})",
+                    f.name,
+                    f.size,
+                    f.sha256.get(..12).unwrap_or(&f.sha256)
+                )
+            })
+            .collect();
+        let reference = if record.reference.is_empty() {
+            String::new()
+        } else {
+            format!("  [Ref {}]", record.reference)
+        };
+        println!(
+            "{}  {:<15}  {}  {}{}{}",
+            record.timestamp.format("%Y-%m-%d %H:%M:%S"),
+            record.outcome.to_string(),
+            record.recipients.join(", "),
+            files.join(", "),
+            reference,
+            if record.secure { "  [secure]" } else { "" }
+        );
+    }
+    Ok(())
+}
+
+fn list_scheduled() -> Result<()> {
+    let pending = schedule::list(&schedule::schedule_dir())?;
+    if pending.is_empty() {
+        println!("No scheduled password emails");
+    }
+    for p in &pending {
+        println!(
+            "{}  {}  {}{}",
+            p.id,
+            p.send_at.format("%Y-%m-%d %H:%M:%S"),
+            p.to.join(", "),
+            p.profile
+                .as_ref()
+                .map(|name| format!("  [profile {}]", name))
+                .unwrap_or_default()
+        );
+    }
+    Ok(())
+}
+
+fn cancel_scheduled(id: &str) -> Result<()> {
+    let dir = schedule::schedule_dir();
+    let pending = schedule::list(&dir)?.into_iter().find(|p| p.id == id);
+    // Removing it is what stops a waiting ppap or `ppap resume` from sending it.
+    let Some(mut pending) = pending else {
+        return Err(anyhow::anyhow!(
+            "No scheduled password email with reference {}",
+            id
+        ))
+        .categorize(FailureKind::Input);
+    };
+    if !schedule::remove(&dir, id)? {
+        println!("The password email of {} was sent in the meantime", id);
+        return Ok(());
+    }
+    pending.record.timestamp = chrono::Utc::now();
+    pending.record.error = Some("Password email was cancelled".to_string());
+    if let Err(e) = history::append(&history::history_file_path(), &pending.record) {
+        println!("[WARNING] Failed to record transfer history: {}", e);
+    }
+    println!("Cancelled the password email of {}", id);
+    Ok(())
+}
+
+/// Sends the scheduled password emails, each once it is due.
+async fn resume(args: ResumeArgs) -> Result<()> {
+    let dir = schedule::schedule_dir();
+    let pending: Vec<_> = schedule::list(&dir)?
+        .into_iter()
+        .filter(|p| args.id.as_ref().is_none_or(|id| &p.id == id))
+        .collect();
+    match (&args.id, pending.is_empty()) {
+        (Some(id), true) => {
+            return Err(anyhow::anyhow!(
+                "No scheduled password email with reference {}",
+                id
+            ))
+            .categorize(FailureKind::Input)
+        }
+        (None, true) => println!("No scheduled password emails"),
+        _ => {}
+    }
+
+    for mut p in pending {
+        let settings = Settings::load(p.profile.clone()).categorize(FailureKind::Config)?;
+        let config = SendGridConfig::from_env(&settings).categorize(FailureKind::Config)?;
+        let sender = SendGridSender::new(&config).categorize(FailureKind::Config)?;
+
+        println!(
+            "Password email of {} to {} is due {}",
+            p.id,
+            p.to.join(", "),
+            p.send_at.format("%Y-%m-%d %H:%M:%S")
+        );
+        schedule::wait_until(p.send_at).await;
+        // The waiting ppap, another `ppap resume` or `ppap schedule cancel` may have taken it.
+        if !schedule::remove(&dir, &p.id)? {
+            println!(
+                "The password email of {} was cancelled or already sent",
+                p.id
+            );
+            continue;
+        }
+        let sent = sender
+            .send_email(&p.to, &p.cc, &p.subject, &p.body, &p.headers)
+            .await;
+        if sent.is_err() {
+            schedule::save(&dir, &p)?;
+        }
+        let receipt = sent.categorize(FailureKind::PasswordDelivery)?;
+
+        p.record.timestamp = chrono::Utc::now();
+        p.record.message_ids.push(receipt.message_id.clone());
+        p.record.receipts.push(receipt);
+        p.record.outcome = history::Outcome::Delivered;
+        p.record.error = None;
+        if let Err(e) = history::append(&history::history_file_path(), &p.record) {
+            println!("[WARNING] Failed to record transfer history: {}", e);
+        }
+        println!("Email #2 sent successfully!");
     }
+    Ok(())
+}
+
+fn verify_audit_log() -> Result<()> {
+    let log_path = history::history_file_path();
+    let key = audit::load_key(&audit::key_path())?;
+    let entries = history::read_entries(&log_path)?;
+    let head = audit::read_head(&log_path)?;
+
+    println!("Verifying: {}", log_path.display());
+    if key.is_none() {
+        println!("No audit key found, HMACs are not checked");
+    }
+
+    let problems = audit::verify(&entries, head.as_ref(), key.as_deref());
+    if problems.is_empty() {
+        println!("OK: {} record(s), chain intact", entries.len());
+        return Ok(());
+    }
+    for problem in &problems {
+        println!("  {}", problem);
+    }
+    Err(anyhow::anyhow!(
+        "Audit log verification failed with {} problem(s)",
+        problems.len()
+    ))
+}
 
+fn init_audit_key() -> Result<()> {
+    let path = audit::key_path();
+    audit::generate_key(&path)?;
+    let key = audit::load_key(&path)?.unwrap_or_default();
+    history::seal(&history::history_file_path(), &key)?;
+    println!("Audit key written to {}", path.display());
+    println!("New transfer records will carry an HMAC. Keep a copy of this key in a safe place.");
     Ok(())
 }
 
-fn get_help_text() -> String {
-    use clap::CommandFactory;
-    Opts::command().render_help().to_string()
+fn show_config(settings: &Settings) -> Result<()> {
+    println!(
+        "Config file: {} ({})",
+        settings.config_path.display(),
+        if settings.config_found {
+            "found"
+        } else {
+            "not found"
+        }
+    );
+    println!(
+        "Profile: {}",
+        settings.profile_name.as_deref().unwrap_or("(none)")
+    );
+
+    let (api_key, protocol, from_address) = SendGridConfig::env_values(settings)?;
+    let policy = settings.profile.password_policy();
+    let templates = settings.profile.templates();
+
+    println!(
+        "API Key: {}",
+        api_key
+            .as_deref()
+            .map(config::mask_secret)
+            .unwrap_or_else(|| "(not set)".to_string())
+    );
+    match &settings.profile.smtp {
+        Some(relay) => println!("Provider: smtp ({}:{})", relay.host, relay.port),
+        None => println!("Provider: {}", protocol.as_deref().unwrap_or("rest")),
+    }
+    println!(
+        "From Address: {}",
+        from_address.as_deref().unwrap_or("(not set)")
+    );
+    if let Some(from_name) = &settings.profile.from_name {
+        println!("From Name: {}", from_name);
+    }
+    if let Some(reply_to) = &settings.profile.reply_to {
+        println!("Reply-To: {}", reply_to);
+    }
+    for (name, value) in &settings.profile.headers {
+        println!("Header: {}: {}", name, value);
+    }
+    println!(
+        "Password Delay: {}",
+        settings
+            .profile
+            .delay_hours
+            .map(|h| format!("{} hours", h))
+            .unwrap_or_else(|| "none".to_string())
+    );
+    println!(
+        "Password Policy: {} characters, symbols {}",
+        policy.length,
+        if policy.symbols { "on" } else { "off" }
+    );
+    println!(
+        "Strip Metadata: {}",
+        if settings.profile.strip_metadata.unwrap_or(false) {
+            "on"
+        } else {
+            "off"
+        }
+    );
+    println!(
+        "Message Size Limit: {}",
+        settings
+            .profile
+            .max_message_mb
+            .map(|mb| format!("{} MB", mb))
+            .unwrap_or_else(|| "30 MB (SendGrid)".to_string())
+    );
+    println!(
+        "Delivery: {}",
+        match (
+            settings.profile.delivery.unwrap_or_default(),
+            &settings.profile.storage
+        ) {
+            (transfer::Delivery::Attachment, _) => "attachment".to_string(),
+            (transfer::Delivery::Link, Some(storage)) => {
+                format!("link ({}/{})", storage.endpoint, storage.bucket)
+            }
+            (transfer::Delivery::Link, None) => "link (storage not configured)".to_string(),
+            (transfer::Delivery::Serve, _) => match &settings.profile.serve {
+                Some(serve) => format!("serve ({})", serve.public_url),
+                None => "serve (serve not configured)".to_string(),
+            },
+        }
+    );
+    println!(
+        "S/MIME: {}",
+        match &settings.profile.smime {
+            Some(smime) if smime.encrypt =>
+                format!("sign and encrypt ({})", smime.pkcs12.display()),
+            Some(smime) => format!("sign ({})", smime.pkcs12.display()),
+            None => "off".to_string(),
+        }
+    );
+    let sendgrid = settings.profile.sendgrid.clone().unwrap_or_default();
+    if !sendgrid.categories.is_empty() {
+        println!("Categories: {}", sendgrid.categories.join(", "));
+    }
+    if settings.profile.sandbox.unwrap_or(false) {
+        println!("Sandbox: on (nothing is delivered)");
+    }
+    println!(
+        "Click/Open Tracking: {}",
+        if sendgrid.tracking {
+            "account settings"
+        } else {
+            "off"
+        }
+    );
+    println!(
+        "DKIM: {}",
+        match &settings.profile.dkim {
+            Some(dkim) => format!("{}._domainkey.{}", dkim.selector, dkim.domain),
+            None => "off".to_string(),
+        }
+    );
+    println!("Templates:");
+    println!("  ZIP Subject: {}", templates.zip_subject);
+    println!("  ZIP Body: {:?}", templates.zip_body);
+    println!("  Password Subject: {}", templates.password_subject);
+    println!("  Password Body: {:?}", templates.password_body);
+    println!("  Link Body: {:?}", templates.link_body);
+    println!("  Public Key Body: {:?}", templates.public_key_body);
+
+    Ok(())
 }
 
-fn get_password_to_send(real_password: &str, secure_mode: bool) -> String {
-    if secure_mode {
-        use rand::distributions::Alphanumeric;
-        use rand::{thread_rng, Rng};
-        thread_rng()
-            .sample_iter(&Alphanumeric)
-            .take(real_password.len())
-            .map(char::from)
-            .collect()
+fn set_key(settings: &Settings, stdin: bool) -> Result<()> {
+    let api_key = if stdin {
+        let mut line = String::new();
+        std::io::stdin().read_line(&mut line)?;
+        line.trim().to_string()
     } else {
-        real_password.to_string()
+        dialoguer::Password::new()
+            .with_prompt("SendGrid API key")
+            .interact()?
+    };
+    if api_key.is_empty() {
+        return Err(anyhow::anyhow!("SendGrid API key cannot be empty"));
     }
+
+    let account = credentials::account_name(settings.profile_name.as_deref());
+    let backend =
+        credentials::store_secret(
+            &account,
+            &api_key,
+            || match credentials::passphrase_from_env() {
+                Some(passphrase) => Ok(passphrase),
+                None => Ok(dialoguer::Password::new()
+                    .with_prompt("Passphrase for the credentials file")
+                    .with_confirmation("Confirm passphrase", "Passphrases do not match")
+                    .interact()?),
+            },
+        )?;
+
+    println!("API key stored in {} ({})", backend, account);
+    Ok(())
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
-    async_main().await
+async fn main() -> ExitCode {
+    match async_main().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            if output::is_json() {
+                let kind = error::kind_of(&e);
+                let result = serde_json::json!({
+                    "status": "error",
+                    "category": kind.map_or("other", FailureKind::name),
+                    "exit_code": kind.map_or(1, FailureKind::exit_code),
+                    "error": format!("{:#}", e),
+                });
+                println!("{}", result);
+            } else {
+                eprintln!("Error: {:?}", e);
+            }
+            error::exit_code(&e)
+        }
+    }
 }
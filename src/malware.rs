@@ -0,0 +1,201 @@
+use anyhow::Context;
+use serde::Deserialize;
+use std::path::PathBuf;
+
+/// External virus scanner run on the input files, configured as `[malware_scan]` in the policy.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MalwareScan {
+    /// Command and arguments. `{file}` is replaced by the file, otherwise it is appended.
+    /// Exit status 0 means clean and 1 means infected, as with `clamscan`.
+    pub command: Option<Vec<String>>,
+    /// clamd socket, scanned with `INSTREAM` so clamd does not need to read the file.
+    pub clamd_socket: Option<PathBuf>,
+}
+
+impl MalwareScan {
+    pub fn is_enabled(&self) -> bool {
+        self.command.is_some() || self.clamd_socket.is_some()
+    }
+
+    /// Returns one description per infected file. Scanner failures are errors.
+    pub fn scan(&self, files: &[String]) -> anyhow::Result<Vec<String>> {
+        let mut infected = Vec::new();
+        for file in files {
+            let verdict = match (&self.command, &self.clamd_socket) {
+                (Some(command), _) => scan_with_command(command, file)?,
+                (None, Some(socket)) => scan_with_clamd(socket, file)?,
+                (None, None) => None,
+            };
+            if let Some(signature) = verdict {
+                infected.push(format!("{}: {}", file, signature));
+            }
+        }
+        Ok(infected)
+    }
+}
+
+fn scan_with_command(command: &[String], file: &str) -> anyhow::Result<Option<String>> {
+    let (program, args) = command
+        .split_first()
+        .ok_or_else(|| anyhow::anyhow!("malware_scan.command is empty"))?;
+    let mut args: Vec<String> = args.iter().map(|a| a.replace("{file}", file)).collect();
+    if !command.iter().any(|a| a.contains("{file}")) {
+        args.push(file.to_string());
+    }
+
+    let output = std::process::Command::new(program)
+        .args(&args)
+        .output()
+        .with_context(|| format!("Failed to run malware scanner: {}", program))?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    match output.status.code() {
+        Some(0) => Ok(None),
+        Some(1) => Ok(Some(
+            stdout
+                .lines()
+                .find(|line| line.contains("FOUND"))
+                .unwrap_or("infected")
+                .trim()
+                .to_string(),
+        )),
+        _ => Err(anyhow::anyhow!(
+            "Malware scanner failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )),
+    }
+}
+
+#[cfg(unix)]
+fn scan_with_clamd(socket: &std::path::Path, file: &str) -> anyhow::Result<Option<String>> {
+    use std::io::{Read, Write};
+
+    let mut stream = std::os::unix::net::UnixStream::connect(socket)
+        .with_context(|| format!("Failed to connect to clamd: {}", socket.display()))?;
+    let mut input =
+        std::fs::File::open(file).with_context(|| format!("Failed to read file: {}", file))?;
+
+    stream.write_all(b"zINSTREAM\0")?;
+    let mut chunk = vec![0u8; 64 * 1024];
+    loop {
+        let n = input.read(&mut chunk)?;
+        stream.write_all(&(n as u32).to_be_bytes())?;
+        if n == 0 {
+            break;
+        }
+        stream.write_all(&chunk[..n])?;
+    }
+
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply)?;
+    let reply = reply.trim_end_matches('\0').trim();
+    match reply.strip_prefix("stream: ") {
+        Some("OK") => Ok(None),
+        Some(found) if found.ends_with(" FOUND") => Ok(Some(found.to_string())),
+        _ => Err(anyhow::anyhow!("clamd could not scan {}: {}", file, reply)),
+    }
+}
+
+#[cfg(not(unix))]
+fn scan_with_clamd(_socket: &std::path::Path, _file: &str) -> anyhow::Result<Option<String>> {
+    Err(anyhow::anyhow!(
+        "malware_scan.clamd_socket is only supported on Unix"
+    ))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use tempfile::tempdir;
+
+    const EICAR: &str = "X5O!P%@AP[4\\PZX54(P^)7CC)7}$EICAR-STANDARD-ANTIVIRUS-TEST-FILE!$H+H*";
+
+    fn write(dir: &std::path::Path, name: &str, content: &str) -> String {
+        let path = dir.join(name);
+        std::fs::write(&path, content).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn test_command_scanner() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let clean = write(temp_dir.path(), "clean.txt", "hello");
+        let eicar = write(temp_dir.path(), "eicar.txt", EICAR);
+        let scan = MalwareScan {
+            command: Some(
+                [
+                    "sh",
+                    "-c",
+                    "grep -q EICAR \"$1\" && echo \"$1: Eicar FOUND\" && exit 1; exit 0",
+                    "sh",
+                    "{file}",
+                ]
+                .map(String::from)
+                .to_vec(),
+            ),
+            clamd_socket: None,
+        };
+
+        let infected = scan.scan(&[clean, eicar.clone()])?;
+        assert_eq!(infected, vec![format!("{0}: {0}: Eicar FOUND", eicar)]);
+
+        let broken = MalwareScan {
+            command: Some(vec![
+                "sh".to_string(),
+                "-c".to_string(),
+                "exit 2".to_string(),
+            ]),
+            clamd_socket: None,
+        };
+        assert!(broken.scan(&[eicar]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_clamd_instream() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let socket = temp_dir.path().join("clamd.sock");
+        let listener = std::os::unix::net::UnixListener::bind(&socket)?;
+
+        // Minimal clamd: reads one INSTREAM request per connection.
+        let server = std::thread::spawn(move || {
+            for _ in 0..2 {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut command = [0u8; 10];
+                stream.read_exact(&mut command).unwrap();
+                assert_eq!(&command, b"zINSTREAM\0");
+                let mut data = Vec::new();
+                loop {
+                    let mut len = [0u8; 4];
+                    stream.read_exact(&mut len).unwrap();
+                    let len = u32::from_be_bytes(len) as usize;
+                    if len == 0 {
+                        break;
+                    }
+                    let mut chunk = vec![0u8; len];
+                    stream.read_exact(&mut chunk).unwrap();
+                    data.extend(chunk);
+                }
+                let reply: &[u8] = if data.windows(5).any(|w| w == b"EICAR") {
+                    b"stream: Eicar-Signature FOUND\0"
+                } else {
+                    b"stream: OK\0"
+                };
+                stream.write_all(reply).unwrap();
+            }
+        });
+
+        let clean = write(temp_dir.path(), "clean.txt", "hello");
+        let eicar = write(temp_dir.path(), "eicar.txt", EICAR);
+        let scan = MalwareScan {
+            command: None,
+            clamd_socket: Some(socket),
+        };
+        let infected = scan.scan(&[clean, eicar.clone()])?;
+        server.join().unwrap();
+        assert_eq!(infected, vec![format!("{}: Eicar-Signature FOUND", eicar)]);
+        Ok(())
+    }
+}
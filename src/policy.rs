@@ -0,0 +1,307 @@
+use crate::address::{self, Mailbox};
+use crate::dlp::DlpPolicy;
+use crate::email::sender;
+use crate::history::{self, Outcome, TransferRecord};
+use crate::malware::MalwareScan;
+use crate::output;
+use anyhow::Context;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+
+/// Rules checked before the ZIP email is sent.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    /// When not empty, every recipient must be in one of these domains.
+    #[serde(default)]
+    pub allowed_domains: Vec<String>,
+    #[serde(default)]
+    pub denied_domains: Vec<String>,
+    /// Ask before sending to an address that never received a transfer.
+    #[serde(default)]
+    pub confirm_new_recipients: bool,
+    pub max_attachment_mb: Option<u64>,
+    /// Domains whose transfers must send the password to a separate address.
+    #[serde(default)]
+    pub separate_password_domains: Vec<String>,
+    /// Content scan of the files before they are archived.
+    #[serde(default)]
+    pub dlp: DlpPolicy,
+    #[serde(default)]
+    pub malware_scan: MalwareScan,
+}
+
+/// The addresses and archive of one transfer, as seen by the policy.
+#[derive(Debug)]
+pub struct Envelope<'a> {
+    pub to: &'a [String],
+    pub cc: &'a [String],
+    pub password_to: &'a [String],
+    pub archive_size: u64,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Evaluation {
+    pub violations: Vec<String>,
+    /// Addresses that need confirmation because they never received a transfer.
+    pub new_recipients: Vec<String>,
+}
+
+impl Policy {
+    pub fn evaluate(&self, envelope: &Envelope, known: &HashSet<String>) -> Evaluation {
+        let mut evaluation = Evaluation::default();
+        let addresses = envelope
+            .to
+            .iter()
+            .chain(envelope.cc)
+            .chain(envelope.password_to);
+
+        for address in addresses {
+            let domain = domain_of(address);
+            if self
+                .denied_domains
+                .iter()
+                .any(|pattern| domain_matches(pattern, &domain))
+            {
+                evaluation
+                    .violations
+                    .push(format!("{}: domain '{}' is denied", address, domain));
+            } else if !self.allowed_domains.is_empty()
+                && !self
+                    .allowed_domains
+                    .iter()
+                    .any(|pattern| domain_matches(pattern, &domain))
+            {
+                evaluation
+                    .violations
+                    .push(format!("{}: domain '{}' is not allowed", address, domain));
+            }
+
+            if self.confirm_new_recipients
+                && !known.contains(&address::email_of(address).to_lowercase())
+                && !evaluation.new_recipients.contains(address)
+            {
+                evaluation.new_recipients.push(address.clone());
+            }
+        }
+
+        if envelope.password_to.is_empty() {
+            for address in envelope.to.iter().chain(envelope.cc) {
+                let domain = domain_of(address);
+                if self
+                    .separate_password_domains
+                    .iter()
+                    .any(|pattern| domain_matches(pattern, &domain))
+                {
+                    evaluation.violations.push(format!(
+                        "{}: domain '{}' requires the password to go to a separate recipient",
+                        address, domain
+                    ));
+                }
+            }
+        }
+
+        if let Some(max) = self.max_attachment_mb {
+            if envelope.archive_size > max.saturating_mul(sender::MB) {
+                evaluation.violations.push(format!(
+                    "archive is {} bytes, the limit is {} MB",
+                    envelope.archive_size, max
+                ));
+            }
+        }
+        evaluation
+    }
+}
+
+fn domain_of(address: &str) -> String {
+    Mailbox::parse(address)
+        .map(|mailbox| mailbox.domain().to_string())
+        .unwrap_or_default()
+}
+
+/// `example.com` matches only itself, `*.example.com` matches its subdomains too.
+fn domain_matches(pattern: &str, domain: &str) -> bool {
+    let pattern = pattern.trim().to_lowercase();
+    match pattern.strip_prefix("*.") {
+        Some(parent) => domain == parent || domain.ends_with(&format!(".{}", parent)),
+        None => domain == pattern,
+    }
+}
+
+/// Lowercased addresses that were sent at least the ZIP email before.
+pub fn known_recipients(records: &[TransferRecord]) -> HashSet<String> {
+    records
+        .iter()
+        .filter(|record| record.outcome != Outcome::Failed)
+        .flat_map(|record| {
+            record
+                .recipients
+                .iter()
+                .chain(&record.cc)
+                .chain(&record.password_recipients)
+        })
+        .map(|address| address::email_of(address).to_lowercase())
+        .collect()
+}
+
+/// Fails when the envelope violates the policy. First-time recipients are accepted with
+/// `assume_yes`, otherwise the user is asked when `prompt` is set and stdin is a terminal.
+pub fn enforce(
+    policy: &Policy,
+    envelope: &Envelope,
+    assume_yes: bool,
+    prompt: bool,
+) -> anyhow::Result<()> {
+    let known = if policy.confirm_new_recipients {
+        known_recipients(&history::read(&history::history_file_path())?)
+    } else {
+        HashSet::new()
+    };
+    let evaluation = policy.evaluate(envelope, &known);
+    if !evaluation.violations.is_empty() {
+        return Err(anyhow::anyhow!(
+            "Blocked by policy:\n  {}",
+            evaluation.violations.join("\n  ")
+        ));
+    }
+    if evaluation.new_recipients.is_empty() || assume_yes {
+        return Ok(());
+    }
+
+    let new_recipients = evaluation.new_recipients.join(", ");
+    if prompt && std::io::stdin().is_terminal() && !output::is_json() {
+        let confirmed = dialoguer::Confirm::new()
+            .with_prompt(format!(
+                "First transfer to {}. Send anyway?",
+                new_recipients
+            ))
+            .default(false)
+            .interact()?;
+        if confirmed {
+            return Ok(());
+        }
+    }
+    Err(anyhow::anyhow!(
+        "First-time recipient(s) need confirmation: {} (use --yes to confirm)",
+        new_recipients
+    ))
+}
+
+pub fn policy_file_path() -> PathBuf {
+    std::env::var_os("PPAP_POLICY")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| crate::config::config_dir().join("policy.toml"))
+}
+
+/// Reads the policy. Without a policy file everything is allowed.
+pub fn load(path: &Path) -> anyhow::Result<Policy> {
+    if !path.exists() {
+        return Ok(Policy::default());
+    }
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read policy: {}", path.display()))?;
+    toml::from_str(&content).with_context(|| format!("Failed to parse policy: {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> Policy {
+        toml::from_str(
+            r#"
+allowed_domains = ["acme.example", "*.ours.example"]
+denied_domains = ["old.acme.example"]
+confirm_new_recipients = true
+max_attachment_mb = 1
+separate_password_domains = ["acme.example"]
+"#,
+        )
+        .unwrap()
+    }
+
+    fn addresses(list: &[&str]) -> Vec<String> {
+        list.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn test_domain_rules() {
+        let to = addresses(&["a@acme.example", "b@gmail.example"]);
+        let cc = addresses(&["c@tokyo.ours.example"]);
+        let password_to = addresses(&["pw@ours.example"]);
+        let known = HashSet::from(["a@acme.example".to_string()]);
+
+        let evaluation = policy().evaluate(
+            &Envelope {
+                to: &to,
+                cc: &cc,
+                password_to: &password_to,
+                archive_size: 100,
+            },
+            &known,
+        );
+        assert_eq!(
+            evaluation.violations,
+            vec!["b@gmail.example: domain 'gmail.example' is not allowed"]
+        );
+        assert_eq!(
+            evaluation.new_recipients,
+            vec!["b@gmail.example", "c@tokyo.ours.example", "pw@ours.example"]
+        );
+    }
+
+    #[test]
+    fn test_denied_domain_wins() {
+        let mut policy = policy();
+        policy.allowed_domains.push("*.acme.example".to_string());
+        let to = addresses(&["a@OLD.acme.example"]);
+
+        let evaluation = policy.evaluate(
+            &Envelope {
+                to: &to,
+                cc: &[],
+                password_to: &addresses(&["pw@ours.example"]),
+                archive_size: 0,
+            },
+            &HashSet::new(),
+        );
+        assert_eq!(
+            evaluation.violations,
+            vec!["a@OLD.acme.example: domain 'old.acme.example' is denied"]
+        );
+    }
+
+    #[test]
+    fn test_separate_password_and_size() {
+        let to = addresses(&["a@acme.example"]);
+        let evaluation = policy().evaluate(
+            &Envelope {
+                to: &to,
+                cc: &[],
+                password_to: &[],
+                archive_size: 2 * sender::MB,
+            },
+            &HashSet::new(),
+        );
+        assert_eq!(evaluation.violations.len(), 2);
+        assert!(evaluation.violations[0].contains("separate recipient"));
+        assert!(evaluation.violations[1].contains("the limit is 1 MB"));
+    }
+
+    #[test]
+    fn test_default_policy_allows_everything() {
+        let to = addresses(&["anyone@anywhere.example"]);
+        let evaluation = Policy::default().evaluate(
+            &Envelope {
+                to: &to,
+                cc: &[],
+                password_to: &[],
+                archive_size: u64::MAX,
+            },
+            &HashSet::new(),
+        );
+        assert_eq!(evaluation, Evaluation::default());
+    }
+}
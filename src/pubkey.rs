@@ -0,0 +1,466 @@
+use crate::address;
+use crate::zip::Encryption;
+use anyhow::Context;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+
+/// Public keys of one recipient, from `keys.toml` or the `keys` of a contact.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PublicKeys {
+    /// age recipients such as `age1...`.
+    #[serde(default)]
+    pub age: Vec<String>,
+    /// Key IDs or fingerprints in the local gpg keyring.
+    #[serde(default)]
+    pub pgp: Vec<String>,
+    /// PEM certificates for S/MIME encrypted email.
+    #[serde(default)]
+    pub smime: Vec<PathBuf>,
+}
+
+impl PublicKeys {
+    pub fn is_empty(&self) -> bool {
+        self.age.is_empty() && self.pgp.is_empty() && self.smime.is_empty()
+    }
+}
+
+/// Public keys by lowercase email address.
+pub type Keyring = BTreeMap<String, PublicKeys>;
+
+pub fn keyring_file_path() -> PathBuf {
+    std::env::var_os("PPAP_KEYS")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| crate::config::config_dir().join("keys.toml"))
+}
+
+/// Reads the keyring. A missing file is an empty keyring.
+pub fn load(path: &Path) -> anyhow::Result<Keyring> {
+    if !path.exists() {
+        return Ok(Keyring::new());
+    }
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read keyring: {}", path.display()))?;
+    let keyring: Keyring = toml::from_str(&content)
+        .with_context(|| format!("Failed to parse keyring: {}", path.display()))?;
+    Ok(keyring
+        .into_iter()
+        .map(|(email, keys)| (email.to_lowercase(), keys))
+        .collect())
+}
+
+/// Keys that every address can decrypt with, looked up in `keyrings` in order.
+/// For OpenPGP an address without a key becomes `<email>`, which `encrypt` resolves to
+/// the keys gpg itself considers valid for exactly that address.
+pub fn resolve(
+    encryption: Encryption,
+    addresses: &[String],
+    keyrings: &[&Keyring],
+) -> anyhow::Result<Vec<String>> {
+    let mut keys = Vec::new();
+    let mut missing = Vec::new();
+    for recipient in addresses {
+        let email = address::email_of(recipient).to_lowercase();
+        let found = keyrings
+            .iter()
+            .filter_map(|keyring| keyring.get(&email))
+            .map(|keys| match encryption {
+                Encryption::Pgp => &keys.pgp,
+                _ => &keys.age,
+            })
+            .find(|keys| !keys.is_empty());
+        match (found, encryption) {
+            (Some(found), _) => keys.extend(found.iter().cloned()),
+            (None, Encryption::Pgp) => keys.push(format!("<{}>", email)),
+            (None, _) => missing.push(email),
+        }
+    }
+    if !missing.is_empty() {
+        return Err(anyhow::anyhow!(
+            "No age key for {} in {} or the address book",
+            missing.join(", "),
+            keyring_file_path().display()
+        ));
+    }
+    keys.sort();
+    keys.dedup();
+    Ok(keys)
+}
+
+/// S/MIME certificates of every address, looked up in `keyrings` in order.
+pub fn certificates(addresses: &[String], keyrings: &[&Keyring]) -> anyhow::Result<Vec<PathBuf>> {
+    let mut certificates = Vec::new();
+    let mut missing = Vec::new();
+    for recipient in addresses {
+        let email = address::email_of(recipient).to_lowercase();
+        match keyrings
+            .iter()
+            .filter_map(|keyring| keyring.get(&email))
+            .find(|keys| !keys.smime.is_empty())
+        {
+            Some(keys) => certificates.extend(keys.smime.iter().cloned()),
+            None => missing.push(email),
+        }
+    }
+    if !missing.is_empty() {
+        return Err(anyhow::anyhow!(
+            "No S/MIME certificate for {} in {} or the address book",
+            missing.join(", "),
+            keyring_file_path().display()
+        ));
+    }
+    certificates.sort();
+    certificates.dedup();
+    Ok(certificates)
+}
+
+/// Encrypts the archive to the keys as `ARCHIVE.age` or `ARCHIVE.gpg` and removes the
+/// unencrypted archive. Returns the path of the encrypted file.
+pub fn encrypt(
+    encryption: Encryption,
+    archive_path: &str,
+    keys: &[String],
+) -> anyhow::Result<String> {
+    let (output, result) = match encryption {
+        Encryption::Age => {
+            let output = format!("{}.age", archive_path);
+            let result = encrypt_age(archive_path, &output, keys);
+            (output, result)
+        }
+        Encryption::Pgp => {
+            let output = format!("{}.gpg", archive_path);
+            let result = encrypt_pgp(|| Command::new("gpg"), archive_path, &output, keys);
+            (output, result)
+        }
+        _ => return Ok(archive_path.to_string()),
+    };
+    if let Err(e) = result {
+        let _ = std::fs::remove_file(&output);
+        return Err(e);
+    }
+    std::fs::remove_file(archive_path)?;
+    Ok(output)
+}
+
+fn encrypt_age(input: &str, output: &str, keys: &[String]) -> anyhow::Result<()> {
+    let recipients = keys
+        .iter()
+        .map(|key| {
+            age::x25519::Recipient::from_str(key)
+                .map_err(|e| anyhow::anyhow!("Invalid age key '{}': {}", key, e))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let encryptor =
+        age::Encryptor::with_recipients(recipients.iter().map(|r| r as &dyn age::Recipient))?;
+
+    let plaintext =
+        std::fs::read(input).with_context(|| format!("Failed to read archive: {}", input))?;
+    let mut writer = encryptor.wrap_output(std::fs::File::create(output)?)?;
+    writer.write_all(&plaintext)?;
+    writer.finish()?;
+    Ok(())
+}
+
+/// `gpg` is passed in so tests can point it at a throwaway home directory.
+///
+/// Configured keys are vouched for by keys.toml or the address book, so gpg is told to
+/// trust them even when nobody signed them. `<email>` is first narrowed down to the
+/// fingerprints of keys gpg already holds valid for that address.
+fn encrypt_pgp(
+    gpg: impl Fn() -> Command,
+    input: &str,
+    output: &str,
+    keys: &[String],
+) -> anyhow::Result<()> {
+    let mut recipients = Vec::new();
+    for key in keys {
+        match key.strip_prefix('<').and_then(|k| k.strip_suffix('>')) {
+            Some(email) => recipients.extend(valid_pgp_keys(gpg(), email)?),
+            None => recipients.push(key.clone()),
+        }
+    }
+
+    let mut gpg = gpg();
+    gpg.args(["--batch", "--yes", "--trust-model", "always"]);
+    gpg.args(["--output", output, "--encrypt"]);
+    for key in &recipients {
+        gpg.args(["--recipient", key]);
+    }
+    let result = gpg.arg(input).output().context("Failed to run gpg")?;
+    if !result.status.success() {
+        return Err(anyhow::anyhow!(
+            "gpg failed ({}): {}",
+            result.status,
+            String::from_utf8_lossy(&result.stderr).trim()
+        ));
+    }
+    Ok(())
+}
+
+/// Fingerprints of the encryption keys in the gpg keyring with a fully or ultimately
+/// valid user ID for exactly `email`.
+fn valid_pgp_keys(mut gpg: Command, email: &str) -> anyhow::Result<Vec<String>> {
+    let listing = gpg
+        .args(["--batch", "--with-colons", "--list-keys"])
+        .arg(format!("<{}>", email))
+        .output()
+        .context("Failed to run gpg")?;
+    let listing = String::from_utf8_lossy(&listing.stdout);
+
+    let mut fingerprints = Vec::new();
+    // (fingerprint, can encrypt, has a valid user ID for `email`) of the current key
+    let mut current: Option<(Option<String>, bool, bool)> = None;
+    for line in listing.lines().chain(["pub:"]) {
+        let fields: Vec<&str> = line.split(':').collect();
+        match fields[0] {
+            "pub" => {
+                if let Some((Some(fingerprint), true, true)) = current.take() {
+                    fingerprints.push(fingerprint);
+                }
+                let capabilities = fields.get(11).copied().unwrap_or_default();
+                current = Some((None, capabilities.contains('E'), false));
+            }
+            "fpr" => {
+                if let Some((fingerprint @ None, _, _)) = &mut current {
+                    *fingerprint = fields.get(9).map(|f| f.to_string());
+                }
+            }
+            "uid" => {
+                if let Some((_, _, valid)) = &mut current {
+                    let validity = fields.get(1).copied().unwrap_or_default();
+                    let user_id = fields.get(9).copied().unwrap_or_default();
+                    if matches!(validity, "f" | "u")
+                        && address::email_of(user_id).eq_ignore_ascii_case(email)
+                    {
+                        *valid = true;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    if fingerprints.is_empty() {
+        return Err(anyhow::anyhow!(
+            "No valid OpenPGP key for {} in the gpg keyring; sign it or add its fingerprint to {}",
+            email,
+            keyring_file_path().display()
+        ));
+    }
+    Ok(fingerprints)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use tempfile::tempdir;
+
+    fn keyring(email: &str, keys: PublicKeys) -> Keyring {
+        Keyring::from([(email.to_string(), keys)])
+    }
+
+    #[test]
+    fn test_resolve_keys() -> anyhow::Result<()> {
+        let file = keyring(
+            "legal@acme.example",
+            PublicKeys {
+                age: vec!["age1file".to_string()],
+                smime: vec![PathBuf::from("legal.pem")],
+                ..Default::default()
+            },
+        );
+        let contact = keyring(
+            "bob@example.com",
+            PublicKeys {
+                age: vec!["age1contact".to_string()],
+                pgp: vec!["B0B".to_string()],
+                ..Default::default()
+            },
+        );
+        let addresses = vec![
+            "Legal <Legal@acme.example>".to_string(),
+            "bob@example.com".to_string(),
+        ];
+
+        let keys = resolve(Encryption::Age, &addresses, &[&contact, &file])?;
+        assert_eq!(keys, vec!["age1contact", "age1file"]);
+        let keys = resolve(Encryption::Pgp, &addresses, &[&contact, &file])?;
+        assert_eq!(keys, vec!["<legal@acme.example>", "B0B"]);
+
+        let error = resolve(Encryption::Age, &addresses, &[&file]).unwrap_err();
+        assert!(error.to_string().contains("bob@example.com"));
+
+        let found = certificates(&addresses[..1], &[&contact, &file])?;
+        assert_eq!(found, vec![PathBuf::from("legal.pem")]);
+        let error = certificates(&addresses, &[&contact, &file]).unwrap_err();
+        assert!(error.to_string().contains("bob@example.com"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_age_roundtrip() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let archive = temp_dir.path().join("report.pdf.zip");
+        std::fs::write(&archive, b"zip bytes")?;
+        let identity = age::x25519::Identity::generate();
+
+        let encrypted = encrypt(
+            Encryption::Age,
+            archive.to_str().unwrap(),
+            &[identity.to_public().to_string()],
+        )?;
+        assert!(encrypted.ends_with("report.pdf.zip.age"));
+        assert!(!archive.exists());
+
+        let decryptor = age::Decryptor::new(std::fs::File::open(&encrypted)?)?;
+        let mut plaintext = Vec::new();
+        decryptor
+            .decrypt(std::iter::once(&identity as &dyn age::Identity))?
+            .read_to_end(&mut plaintext)?;
+        assert_eq!(plaintext, b"zip bytes");
+
+        assert!(encrypt(Encryption::Age, &encrypted, &["not-a-key".to_string()]).is_err());
+        Ok(())
+    }
+
+    fn gpg_in(home: &Path) -> Command {
+        let mut gpg = Command::new("gpg");
+        gpg.arg("--homedir").arg(home);
+        gpg
+    }
+
+    fn generate_pgp_key(home: &Path, user_id: &str) -> anyhow::Result<()> {
+        std::fs::create_dir_all(home)?;
+        let output = gpg_in(home)
+            .args(["--batch", "--passphrase", "", "--quick-gen-key"])
+            .args([user_id, "future-default", "default", "never"])
+            .output()?;
+        assert!(output.status.success());
+        Ok(())
+    }
+
+    /// Generates a key elsewhere and imports it into `home` without signing it.
+    /// Returns its fingerprint.
+    fn import_unsigned_key(home: &Path, user_id: &str) -> anyhow::Result<String> {
+        let owner = tempdir()?;
+        generate_pgp_key(owner.path(), user_id)?;
+        let exported = gpg_in(owner.path())
+            .args(["--armor", "--export"])
+            .output()?;
+        let key_file = owner.path().join("key.asc");
+        std::fs::write(&key_file, exported.stdout)?;
+        let imported = gpg_in(home)
+            .args(["--batch", "--import"])
+            .arg(&key_file)
+            .output()?;
+        assert!(imported.status.success());
+
+        let listing = gpg_in(owner.path())
+            .args(["--with-colons", "--list-keys"])
+            .output()?;
+        let listing = String::from_utf8(listing.stdout)?;
+        let fingerprint = listing
+            .lines()
+            .find_map(|line| line.strip_prefix("fpr:::::::::"))
+            .and_then(|rest| rest.split(':').next())
+            .unwrap();
+        Ok(fingerprint.to_string())
+    }
+
+    fn gpg_available() -> bool {
+        Command::new("gpg").arg("--version").output().is_ok()
+    }
+
+    #[test]
+    fn test_pgp_roundtrip() -> anyhow::Result<()> {
+        if !gpg_available() {
+            return Ok(());
+        }
+        let temp_dir = tempdir()?;
+        let home = temp_dir.path().join("gnupg");
+        generate_pgp_key(&home, "Alice <alice@example.com>")?;
+
+        let archive = temp_dir.path().join("report.pdf.zip");
+        std::fs::write(&archive, b"zip bytes")?;
+        let output = temp_dir.path().join("report.pdf.zip.gpg");
+        encrypt_pgp(
+            || gpg_in(&home),
+            archive.to_str().unwrap(),
+            output.to_str().unwrap(),
+            &["<alice@example.com>".to_string()],
+        )?;
+
+        let decrypted = gpg_in(&home)
+            .args(["--batch", "--quiet", "--decrypt"])
+            .arg(&output)
+            .output()?;
+        assert_eq!(decrypted.stdout, b"zip bytes");
+
+        let unknown = temp_dir.path().join("unknown.gpg");
+        assert!(encrypt_pgp(
+            || gpg_in(&home),
+            archive.to_str().unwrap(),
+            unknown.to_str().unwrap(),
+            &["<nobody@example.com>".to_string()],
+        )
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_pgp_encrypts_to_configured_unsigned_key() -> anyhow::Result<()> {
+        if !gpg_available() {
+            return Ok(());
+        }
+        let temp_dir = tempdir()?;
+        let home = temp_dir.path().join("gnupg");
+        generate_pgp_key(&home, "Alice <alice@example.com>")?;
+        let fingerprint = import_unsigned_key(&home, "Bob <bob@example.com>")?;
+
+        let archive = temp_dir.path().join("report.pdf.zip");
+        std::fs::write(&archive, b"zip bytes")?;
+        let output = temp_dir.path().join("report.pdf.zip.gpg");
+        encrypt_pgp(
+            || gpg_in(&home),
+            archive.to_str().unwrap(),
+            output.to_str().unwrap(),
+            &[fingerprint],
+        )?;
+        assert!(output.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_pgp_rejects_unvetted_keyring_keys() -> anyhow::Result<()> {
+        if !gpg_available() {
+            return Ok(());
+        }
+        let temp_dir = tempdir()?;
+        let home = temp_dir.path().join("gnupg");
+        generate_pgp_key(&home, "Alice <alice@example.com>")?;
+        // Nobody vouched for either key, though both look like Bob's.
+        import_unsigned_key(&home, "Bob <bob@example.com>")?;
+        import_unsigned_key(&home, "Bob <bob@example.com.attacker.example>")?;
+
+        let archive = temp_dir.path().join("report.pdf.zip");
+        std::fs::write(&archive, b"zip bytes")?;
+        let output = temp_dir.path().join("report.pdf.zip.gpg");
+        let keys = resolve(Encryption::Pgp, &["bob@example.com".to_string()], &[])?;
+        let error = encrypt_pgp(
+            || gpg_in(&home),
+            archive.to_str().unwrap(),
+            output.to_str().unwrap(),
+            &keys,
+        )
+        .unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("No valid OpenPGP key for bob@example.com"));
+        assert!(!output.exists());
+        Ok(())
+    }
+}
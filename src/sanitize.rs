@@ -0,0 +1,360 @@
+use anyhow::Context;
+use serde::Serialize;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+/// What was removed from one file.
+#[derive(Debug, Clone, Serialize)]
+pub struct Removed {
+    pub file: String,
+    pub items: Vec<String>,
+}
+
+/// Copies the files into `dir` and strips metadata from the copies. The originals are not
+/// touched. Returns the paths of the copies and what was removed from each file.
+pub fn sanitized_copies(
+    files: &[String],
+    dir: &Path,
+) -> anyhow::Result<(Vec<String>, Vec<Removed>)> {
+    let result = copy_and_strip(files, dir);
+    if result.is_err() {
+        let _ = std::fs::remove_dir_all(dir);
+    }
+    result
+}
+
+fn copy_and_strip(files: &[String], dir: &Path) -> anyhow::Result<(Vec<String>, Vec<Removed>)> {
+    let mut copies = Vec::with_capacity(files.len());
+    let mut removed = Vec::new();
+    for (i, file) in files.iter().enumerate() {
+        let name = Path::new(file)
+            .file_name()
+            .ok_or_else(|| anyhow::anyhow!("Not a file: {}", file))?;
+        // One directory per input keeps the file name, which is what ends up in the archive.
+        let copy: PathBuf = dir.join(i.to_string()).join(name);
+        std::fs::create_dir_all(copy.parent().unwrap_or(dir))?;
+        std::fs::copy(file, &copy).with_context(|| format!("Failed to copy file: {}", file))?;
+
+        let items = strip(&copy).with_context(|| format!("Failed to strip metadata: {}", file))?;
+        if !items.is_empty() {
+            removed.push(Removed {
+                file: file.clone(),
+                items,
+            });
+        }
+        copies.push(copy.to_string_lossy().into_owned());
+    }
+    Ok((copies, removed))
+}
+
+/// Strips metadata from the file in place, based on its content.
+fn strip(path: &Path) -> anyhow::Result<Vec<String>> {
+    let data = std::fs::read(path)?;
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+
+    let (cleaned, items) = if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        strip_jpeg(&data)
+    } else if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        strip_png(&data)
+    } else if data.starts_with(b"%PDF-") {
+        strip_pdf(data.clone())
+    } else if data.starts_with(b"PK\x03\x04")
+        && matches!(
+            extension.as_str(),
+            "docx" | "docm" | "xlsx" | "xlsm" | "pptx" | "pptm"
+        )
+    {
+        strip_ooxml(&data)?
+    } else {
+        return Ok(Vec::new());
+    };
+
+    if !items.is_empty() {
+        std::fs::write(path, cleaned)?;
+    }
+    Ok(items)
+}
+
+fn push_unique(items: &mut Vec<String>, item: &str) {
+    if !items.iter().any(|i| i == item) {
+        items.push(item.to_string());
+    }
+}
+
+/// Drops APP1 (EXIF, XMP), APP13 (IPTC) and comment segments before the image data.
+fn strip_jpeg(data: &[u8]) -> (Vec<u8>, Vec<String>) {
+    let mut out = data[..2].to_vec();
+    let mut items = Vec::new();
+    let mut pos = 2;
+    while pos + 4 <= data.len() && data[pos] == 0xFF {
+        let marker = data[pos + 1];
+        // Start of scan: the rest is image data.
+        if marker == 0xDA || marker == 0xD9 {
+            break;
+        }
+        let length = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        let end = (pos + 2 + length).min(data.len());
+        let payload = &data[(pos + 4).min(end)..end];
+        let removed = match marker {
+            0xE1 if payload.starts_with(b"Exif\0") => Some("EXIF"),
+            0xE1 if payload.starts_with(b"http://ns.adobe.com/xap/1.0/") => Some("XMP metadata"),
+            0xED => Some("IPTC metadata"),
+            0xFE => Some("comment"),
+            _ => None,
+        };
+        match removed {
+            Some(item) => push_unique(&mut items, item),
+            None => out.extend_from_slice(&data[pos..end]),
+        }
+        pos = end;
+    }
+    out.extend_from_slice(&data[pos.min(data.len())..]);
+    (out, items)
+}
+
+/// Drops the text, EXIF and timestamp chunks. Other chunks keep their CRCs unchanged.
+fn strip_png(data: &[u8]) -> (Vec<u8>, Vec<String>) {
+    let mut out = data[..8].to_vec();
+    let mut items = Vec::new();
+    let mut pos = 8;
+    while pos + 12 <= data.len() {
+        let length =
+            u32::from_be_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]) as usize;
+        let end = (pos + 12 + length).min(data.len());
+        let removed = match &data[pos + 4..pos + 8] {
+            b"eXIf" => Some("EXIF"),
+            b"tEXt" | b"zTXt" | b"iTXt" => Some("text metadata"),
+            b"tIME" => Some("modification time"),
+            _ => None,
+        };
+        match removed {
+            Some(item) => push_unique(&mut items, item),
+            None => out.extend_from_slice(&data[pos..end]),
+        }
+        pos = end;
+    }
+    out.extend_from_slice(&data[pos.min(data.len())..]);
+    (out, items)
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|w| w == needle)
+        .map(|i| from + i)
+}
+
+/// Blanks the document information dictionaries and XMP packets with spaces. Keeping the
+/// length keeps every cross-reference offset valid. Objects inside compressed object
+/// streams are left alone.
+fn strip_pdf(mut data: Vec<u8>) -> (Vec<u8>, Vec<String>) {
+    let mut items = Vec::new();
+
+    let mut from = 0;
+    while let Some(at) = find(&data, b"/Info", from) {
+        from = at + 5;
+        let Some((number, generation)) = parse_reference(&data[from..]) else {
+            continue;
+        };
+        let header = format!("{} {} obj", number, generation);
+        let mut search = 0;
+        while let Some(obj) = find(&data, header.as_bytes(), search) {
+            search = obj + header.len();
+            if obj > 0 && data[obj - 1].is_ascii_digit() {
+                continue;
+            }
+            if blank_dictionary(&mut data, search) {
+                push_unique(&mut items, "document info");
+            }
+        }
+    }
+
+    let mut from = 0;
+    while let Some(begin) = find(&data, b"<?xpacket begin", from) {
+        let Some(start) = find(&data, b"?>", begin).map(|i| i + 2) else {
+            break;
+        };
+        let Some(end) = find(&data, b"<?xpacket end", start) else {
+            break;
+        };
+        if data[start..end].iter().any(|b| !b.is_ascii_whitespace()) {
+            data[start..end].fill(b' ');
+            push_unique(&mut items, "XMP metadata");
+        }
+        from = end;
+    }
+    (data, items)
+}
+
+/// Parses `12 0 R` after optional whitespace.
+fn parse_reference(data: &[u8]) -> Option<(u32, u32)> {
+    let text = std::str::from_utf8(&data[..data.len().min(32)])
+        .ok()
+        .or_else(|| {
+            let valid = std::str::from_utf8(&data[..data.len().min(32)])
+                .err()?
+                .valid_up_to();
+            std::str::from_utf8(&data[..valid]).ok()
+        })?;
+    let mut parts = text.split_ascii_whitespace();
+    let number = parts.next()?.parse().ok()?;
+    let generation = parts.next()?.parse().ok()?;
+    parts
+        .next()
+        .filter(|r| r.starts_with('R'))
+        .map(|_| (number, generation))
+}
+
+/// Overwrites the inside of the dictionary that starts after `from` with spaces.
+fn blank_dictionary(data: &mut [u8], from: usize) -> bool {
+    let Some(open) = find(data, b"<<", from) else {
+        return false;
+    };
+    let start = open + 2;
+    let mut depth = 1;
+    let mut string_depth = 0;
+    let mut i = start;
+    while i + 1 < data.len() {
+        match (data[i], data[i + 1]) {
+            (b'\\', _) if string_depth > 0 => i += 1,
+            (b'(', _) => string_depth += 1,
+            (b')', _) if string_depth > 0 => string_depth -= 1,
+            (b'<', b'<') if string_depth == 0 => {
+                depth += 1;
+                i += 1;
+            }
+            (b'>', b'>') if string_depth == 0 => {
+                depth -= 1;
+                if depth == 0 {
+                    let blanked = data[start..i].iter().any(|b| !b.is_ascii_whitespace());
+                    data[start..i].fill(b' ');
+                    return blanked;
+                }
+                i += 1;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    false
+}
+
+const EMPTY_CORE: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<cp:coreProperties xmlns:cp="http://schemas.openxmlformats.org/package/2006/metadata/core-properties" xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:dcterms="http://purl.org/dc/terms/" xmlns:dcmitype="http://purl.org/dc/dcmitype/" xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance"/>"#;
+const EMPTY_APP: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Properties xmlns="http://schemas.openxmlformats.org/officeDocument/2006/extended-properties" xmlns:vt="http://schemas.openxmlformats.org/officeDocument/2006/docPropsVTypes"/>"#;
+const EMPTY_CUSTOM: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Properties xmlns="http://schemas.openxmlformats.org/officeDocument/2006/custom-properties" xmlns:vt="http://schemas.openxmlformats.org/officeDocument/2006/docPropsVTypes"/>"#;
+
+/// Replaces the docProps parts with empty ones. The parts stay in place because the
+/// package relationships point at them.
+fn strip_ooxml(data: &[u8]) -> anyhow::Result<(Vec<u8>, Vec<String>)> {
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(data))?;
+    let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    let mut items = Vec::new();
+
+    for i in 0..archive.len() {
+        let name = archive.by_index_raw(i)?.name().to_string();
+        let replacement = match name.as_str() {
+            "docProps/core.xml" => Some((EMPTY_CORE, "author and revision properties")),
+            "docProps/app.xml" => Some((EMPTY_APP, "application properties")),
+            "docProps/custom.xml" => Some((EMPTY_CUSTOM, "custom properties")),
+            _ => None,
+        };
+        match replacement {
+            Some((empty, item)) => {
+                let mut original = String::new();
+                archive.by_index(i)?.read_to_string(&mut original)?;
+                writer.start_file::<_, ()>(
+                    name,
+                    zip::write::FileOptions::default()
+                        .compression_method(zip::CompressionMethod::Deflated),
+                )?;
+                writer.write_all(empty.as_bytes())?;
+                if original.trim() != empty {
+                    items.push(item.to_string());
+                }
+            }
+            None => writer.raw_copy_file(archive.by_index_raw(i)?)?,
+        }
+    }
+    Ok((writer.finish()?.into_inner(), items))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_strip_jpeg_exif() {
+        let mut jpeg = vec![0xFF, 0xD8];
+        jpeg.extend([0xFF, 0xE0, 0x00, 0x07]);
+        jpeg.extend(b"JFIF\0");
+        jpeg.extend([0xFF, 0xE1, 0x00, 0x0C]);
+        jpeg.extend(b"Exif\0\0ABCD");
+        jpeg.extend([0xFF, 0xFE, 0x00, 0x05]);
+        jpeg.extend(b"hi!");
+        jpeg.extend([0xFF, 0xDA, 0x00, 0x02, 0x12, 0x34, 0xFF, 0xD9]);
+
+        let (cleaned, items) = strip_jpeg(&jpeg);
+        assert_eq!(items, vec!["EXIF", "comment"]);
+        let mut expected = vec![0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x07];
+        expected.extend(b"JFIF\0");
+        expected.extend([0xFF, 0xDA, 0x00, 0x02, 0x12, 0x34, 0xFF, 0xD9]);
+        assert_eq!(cleaned, expected);
+    }
+
+    #[test]
+    fn test_strip_pdf_info_keeps_offsets() {
+        let pdf = b"%PDF-1.4\n1 0 obj\n<< /Type /Catalog >>\nendobj\n\
+                    7 0 obj\n<< /Author (Yamada \\(Legal\\)) /Producer (Word) >>\nendobj\n\
+                    trailer\n<< /Root 1 0 R /Info 7 0 R >>\n%%EOF\n"
+            .to_vec();
+        let (cleaned, items) = strip_pdf(pdf.clone());
+        assert_eq!(items, vec!["document info"]);
+        assert_eq!(cleaned.len(), pdf.len());
+        let text = String::from_utf8(cleaned).unwrap();
+        assert!(!text.contains("Yamada"));
+        assert!(text.contains("<< /Type /Catalog >>"));
+        assert!(text.contains("/Info 7 0 R"));
+    }
+
+    #[test]
+    fn test_sanitized_copies_of_docx() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("memo.docx");
+        let mut writer = zip::ZipWriter::new(std::fs::File::create(&path)?);
+        writer.start_file::<_, ()>("word/document.xml", Default::default())?;
+        writer.write_all(b"<w:document/>")?;
+        writer.start_file::<_, ()>("docProps/core.xml", Default::default())?;
+        writer
+            .write_all(b"<cp:coreProperties><dc:creator>Yamada</dc:creator></cp:coreProperties>")?;
+        writer.finish()?;
+        let original = std::fs::read(&path)?;
+
+        let files = vec![path.to_string_lossy().into_owned()];
+        let (copies, removed) = sanitized_copies(&files, &temp_dir.path().join("clean"))?;
+        assert_eq!(std::fs::read(&path)?, original);
+        assert_eq!(removed[0].items, vec!["author and revision properties"]);
+        assert!(copies[0].ends_with("memo.docx"));
+
+        let mut archive = zip::ZipArchive::new(std::fs::File::open(&copies[0])?)?;
+        let mut core = String::new();
+        archive
+            .by_name("docProps/core.xml")?
+            .read_to_string(&mut core)?;
+        assert!(!core.contains("Yamada"));
+        let mut document = String::new();
+        archive
+            .by_name("word/document.xml")?
+            .read_to_string(&mut document)?;
+        assert_eq!(document, "<w:document/>");
+        Ok(())
+    }
+}
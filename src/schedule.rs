@@ -0,0 +1,166 @@
+use crate::email::sender::Headers;
+use crate::history::TransferRecord;
+use crate::output::{self, say};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// A password email waiting for its `--after` delay, kept on disk so that
+/// `ppap resume` can still send it when the waiting process is gone.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Pending {
+    /// The reference code of the transfer.
+    pub id: String,
+    /// Profile the transfer was sent with.
+    pub profile: Option<String>,
+    pub send_at: DateTime<Utc>,
+    pub to: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cc: Vec<String>,
+    pub subject: String,
+    /// Contains the password, so the file is only readable by its owner.
+    pub body: String,
+    #[serde(default)]
+    pub headers: Headers,
+    /// Appended to the history once the email is sent.
+    pub record: TransferRecord,
+}
+
+pub fn schedule_dir() -> PathBuf {
+    std::env::var_os("PPAP_SCHEDULE")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| crate::config::data_dir().join("schedule"))
+}
+
+fn pending_path(dir: &Path, id: &str) -> PathBuf {
+    dir.join(format!("{}.json", id))
+}
+
+pub fn save(dir: &Path, pending: &Pending) -> anyhow::Result<()> {
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create schedule directory: {}", dir.display()))?;
+    let path = pending_path(dir, &pending.id);
+    crate::credentials::write_private(&path, serde_json::to_string_pretty(pending)?.as_bytes())
+        .with_context(|| format!("Failed to write scheduled email: {}", path.display()))
+}
+
+/// Pending password emails, the next one due first.
+pub fn list(dir: &Path) -> anyhow::Result<Vec<Pending>> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut pending = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("json") {
+            continue;
+        }
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read scheduled email: {}", path.display()))?;
+        pending.push(
+            serde_json::from_str::<Pending>(&content)
+                .with_context(|| format!("Invalid scheduled email: {}", path.display()))?,
+        );
+    }
+    pending.sort_by_key(|p| p.send_at);
+    Ok(pending)
+}
+
+/// Returns whether there was a pending email with this ID. Only one of several
+/// processes removing the same email gets `true`, so it doubles as claiming it for sending.
+pub fn remove(dir: &Path, id: &str) -> anyhow::Result<bool> {
+    let path = pending_path(dir, id);
+    match std::fs::remove_file(&path) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => {
+            Err(e).with_context(|| format!("Failed to remove scheduled email: {}", path.display()))
+        }
+    }
+}
+
+/// Sleeps until `send_at`, showing the time left on the terminal.
+pub async fn wait_until(send_at: DateTime<Utc>) {
+    loop {
+        let remaining = (send_at - Utc::now()).num_seconds();
+        if remaining <= 0 {
+            break;
+        }
+        if !output::is_json() {
+            print!(
+                "\rWaiting... ({:02}:{:02}:{:02} remaining)  ",
+                remaining / 3600,
+                (remaining % 3600) / 60,
+                remaining % 60
+            );
+            let _ = std::io::stdout().flush();
+        }
+        tokio::time::sleep(std::time::Duration::from_secs(remaining.min(60) as u64)).await;
+    }
+    say!("");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::Outcome;
+    use tempfile::tempdir;
+
+    fn pending(id: &str, hours: i64) -> Pending {
+        Pending {
+            id: id.to_string(),
+            profile: None,
+            send_at: Utc::now() + chrono::Duration::hours(hours),
+            to: vec!["a@example.com".to_string()],
+            cc: vec![],
+            subject: format!("Password [Ref {}]", id),
+            body: "Password: secret".to_string(),
+            headers: Headers::new(),
+            record: TransferRecord {
+                timestamp: Utc::now(),
+                recipients: vec!["a@example.com".to_string()],
+                cc: vec![],
+                password_recipients: vec![],
+                files: vec![],
+                archive_size: 10,
+                message_ids: vec![],
+                reference: id.to_string(),
+                receipts: vec![],
+                secure: false,
+                outcome: Outcome::PasswordFailed,
+                error: None,
+            },
+        }
+    }
+
+    #[test]
+    fn test_save_list_and_remove() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let dir = temp_dir.path().join("schedule");
+        save(&dir, &pending("LATER2", 3))?;
+        save(&dir, &pending("SOON23", 1))?;
+
+        let ids: Vec<String> = list(&dir)?.into_iter().map(|p| p.id).collect();
+        assert_eq!(ids, vec!["SOON23", "LATER2"]);
+
+        assert!(remove(&dir, "SOON23")?);
+        assert!(!remove(&dir, "SOON23")?);
+        assert_eq!(list(&dir)?.len(), 1);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_pending_file_is_private() -> anyhow::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        let temp_dir = tempdir()?;
+        save(temp_dir.path(), &pending("K7Q2ZP", 1))?;
+        let mode = std::fs::metadata(temp_dir.path().join("K7Q2ZP.json"))?
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+        Ok(())
+    }
+}
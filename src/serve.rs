@@ -0,0 +1,506 @@
+use crate::output::say;
+use crate::storage::Link;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio_rustls::rustls;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::TlsAcceptor;
+
+const SHARE_FILE: &str = "share.json";
+const DOWNLOAD_LOG: &str = "downloads.jsonl";
+/// Expired and used-up shares are deleted this often.
+const SWEEP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Download server for `delivery = "serve"`, configured as `[profiles.NAME.serve]`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServeConfig {
+    /// Address recipients reach the server at, e.g. `https://files.example.co.jp:8443`.
+    pub public_url: String,
+    /// Defaults to `0.0.0.0:8443`.
+    pub listen: Option<String>,
+    /// PEM certificate chain and private key.
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    /// Downloads allowed per link. Defaults to 1.
+    pub max_downloads: Option<u32>,
+    /// Defaults to 72 hours.
+    pub expires_hours: Option<u64>,
+}
+
+/// An archive waiting to be downloaded, stored as `share.json` next to it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Share {
+    file: String,
+    recipients: Vec<String>,
+    created_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+    max_downloads: u32,
+    downloads: u32,
+}
+
+impl Share {
+    fn is_available(&self, now: DateTime<Utc>) -> bool {
+        now < self.expires_at && self.downloads < self.max_downloads
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct DownloadLogEntry<'a> {
+    timestamp: DateTime<Utc>,
+    /// Only the start of the token, which is enough to tell links apart.
+    token: String,
+    remote_addr: String,
+    user_agent: Option<&'a str>,
+    status: u16,
+}
+
+/// Logs and output show only this much of a token, so they cannot be used to download.
+fn token_prefix(token: &str) -> String {
+    format!("{}...", token.get(..6).unwrap_or(token))
+}
+
+/// `$PPAP_SHARES`, falling back to `~/.local/share/ppap/shares`.
+pub fn shares_dir() -> PathBuf {
+    std::env::var_os("PPAP_SHARES")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| crate::config::data_dir().join("shares"))
+}
+
+/// Copies the archive into the shares directory and returns its download link.
+pub fn publish(
+    dir: &Path,
+    config: &ServeConfig,
+    archive_path: &str,
+    recipients: &[String],
+) -> anyhow::Result<Link> {
+    let token: String = thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect();
+    let file = Path::new(archive_path)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("files.zip")
+        .to_string();
+    let share_dir = dir.join(&token);
+    std::fs::create_dir_all(&share_dir)?;
+    std::fs::copy(archive_path, share_dir.join(&file))
+        .with_context(|| format!("Failed to copy archive to {}", share_dir.display()))?;
+
+    let now = Utc::now();
+    let share = Share {
+        file,
+        recipients: recipients.to_vec(),
+        created_at: now,
+        expires_at: now + chrono::Duration::hours(config.expires_hours.unwrap_or(72) as i64),
+        max_downloads: config.max_downloads.unwrap_or(1),
+        downloads: 0,
+    };
+    std::fs::write(
+        share_dir.join(SHARE_FILE),
+        serde_json::to_vec_pretty(&share)?,
+    )?;
+
+    Ok(Link {
+        url: format!("{}/d/{}", config.public_url.trim_end_matches('/'), token),
+        expires_at: share.expires_at,
+    })
+}
+
+/// Deletes shares that expired or were downloaded as often as allowed, except the ones
+/// in `serving`, which may still be handed back by `release`.
+fn sweep(dir: &Path, now: DateTime<Utc>, serving: &HashSet<String>) -> anyhow::Result<Vec<String>> {
+    let mut removed = Vec::new();
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Ok(removed);
+    };
+    for entry in entries.flatten() {
+        let token = entry.file_name().to_string_lossy().into_owned();
+        let share_file = entry.path().join(SHARE_FILE);
+        if !share_file.exists() || serving.contains(&token) {
+            continue;
+        }
+        let available = std::fs::read(&share_file)
+            .ok()
+            .and_then(|data| serde_json::from_slice::<Share>(&data).ok())
+            .is_some_and(|share| share.is_available(now));
+        if !available {
+            std::fs::remove_dir_all(entry.path())?;
+            removed.push(token);
+        }
+    }
+    Ok(removed)
+}
+
+enum Claim {
+    /// Opened while claiming, so the sweep cannot delete it first.
+    Serve {
+        archive: std::fs::File,
+        file: String,
+    },
+    NotFound,
+    Gone,
+}
+
+/// Counts a download against the share. Callers serialize claims.
+fn claim(dir: &Path, token: &str, now: DateTime<Utc>) -> anyhow::Result<Claim> {
+    if token.is_empty() || !token.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Ok(Claim::NotFound);
+    }
+    let share_file = dir.join(token).join(SHARE_FILE);
+    let Ok(data) = std::fs::read(&share_file) else {
+        return Ok(Claim::NotFound);
+    };
+    let mut share: Share = serde_json::from_slice(&data)?;
+    if !share.is_available(now) {
+        return Ok(Claim::Gone);
+    }
+    let archive = std::fs::File::open(dir.join(token).join(&share.file))?;
+    share.downloads += 1;
+    std::fs::write(&share_file, serde_json::to_vec_pretty(&share)?)?;
+    Ok(Claim::Serve {
+        archive,
+        file: share.file,
+    })
+}
+
+/// Gives back a download that did not reach the recipient.
+fn release(dir: &Path, token: &str) -> anyhow::Result<()> {
+    let share_file = dir.join(token).join(SHARE_FILE);
+    let mut share: Share = serde_json::from_slice(&std::fs::read(&share_file)?)?;
+    share.downloads = share.downloads.saturating_sub(1);
+    std::fs::write(&share_file, serde_json::to_vec_pretty(&share)?)?;
+    Ok(())
+}
+
+fn log_download(dir: &Path, entry: &DownloadLogEntry) -> anyhow::Result<()> {
+    let mut log = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join(DOWNLOAD_LOG))?;
+    writeln!(log, "{}", serde_json::to_string(entry)?)?;
+    Ok(())
+}
+
+fn tls_acceptor(config: &ServeConfig) -> anyhow::Result<TlsAcceptor> {
+    let cert = std::fs::read(&config.cert)
+        .with_context(|| format!("Failed to read certificate: {}", config.cert.display()))?;
+    let certs = CertificateDer::pem_slice_iter(&cert)
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Invalid certificate: {}", config.cert.display()))?;
+    let key = std::fs::read(&config.key)
+        .with_context(|| format!("Failed to read private key: {}", config.key.display()))?;
+    let key = PrivateKeyDer::from_pem_slice(&key)
+        .with_context(|| format!("Invalid private key: {}", config.key.display()))?;
+
+    let provider = Arc::new(rustls::crypto::aws_lc_rs::default_provider());
+    let server_config = rustls::ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+/// Serves `GET /d/TOKEN` over HTTPS until the process is stopped.
+pub async fn run(config: &ServeConfig, dir: &Path) -> anyhow::Result<()> {
+    let acceptor = tls_acceptor(config)?;
+    let listen = config.listen.as_deref().unwrap_or("0.0.0.0:8443");
+    let listener = TcpListener::bind(listen)
+        .await
+        .with_context(|| format!("Failed to listen on {}", listen))?;
+    std::fs::create_dir_all(dir)?;
+    say!("Serving {} on https://{}", dir.display(), listen);
+    say!("Links point to {}", config.public_url);
+    serve(listener, acceptor, dir.to_path_buf()).await
+}
+
+async fn serve(listener: TcpListener, acceptor: TlsAcceptor, dir: PathBuf) -> anyhow::Result<()> {
+    // Tokens being downloaded right now; holding the lock serializes claims.
+    let claims = Arc::new(tokio::sync::Mutex::new(HashSet::new()));
+
+    let sweep_dir = dir.clone();
+    let sweep_claims = claims.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            let serving = sweep_claims.lock().await;
+            match sweep(&sweep_dir, Utc::now(), &serving) {
+                Ok(removed) => {
+                    for token in removed {
+                        say!("Deleted expired share {}", token_prefix(&token));
+                    }
+                }
+                Err(e) => say!("[WARNING] Failed to delete expired shares: {}", e),
+            }
+        }
+    });
+
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let acceptor = acceptor.clone();
+        let dir = dir.clone();
+        let claims = claims.clone();
+        tokio::spawn(async move {
+            let result = async {
+                let stream = acceptor.accept(stream).await?;
+                handle(stream, peer.to_string(), &dir, &claims).await
+            }
+            .await;
+            if let Err(e) = result {
+                say!("[WARNING] {}: {}", peer, e);
+            }
+        });
+    }
+}
+
+async fn handle<S>(
+    mut stream: S,
+    peer: String,
+    dir: &Path,
+    claims: &tokio::sync::Mutex<HashSet<String>>,
+) -> anyhow::Result<()>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 || request.len() > 16 * 1024 {
+            return Ok(());
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+    let request = String::from_utf8_lossy(&request);
+    let mut lines = request.lines();
+    let request_line = lines.next().unwrap_or_default();
+    let user_agent = lines.find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.eq_ignore_ascii_case("user-agent")
+            .then_some(value.trim())
+    });
+
+    let token = match request_line.split_whitespace().collect::<Vec<_>>()[..] {
+        ["GET", path, _] => path.strip_prefix("/d/"),
+        _ => None,
+    };
+    let claim = match token {
+        Some(token) => {
+            let mut serving = claims.lock().await;
+            let claim = claim(dir, token, Utc::now())?;
+            if let Claim::Serve { .. } = claim {
+                serving.insert(token.to_string());
+            }
+            claim
+        }
+        None => Claim::NotFound,
+    };
+
+    if let Some(token) = token {
+        let status = match claim {
+            Claim::Serve { .. } => 200,
+            Claim::NotFound => 404,
+            Claim::Gone => 410,
+        };
+        log_download(
+            dir,
+            &DownloadLogEntry {
+                timestamp: Utc::now(),
+                token: token_prefix(token),
+                remote_addr: peer,
+                user_agent,
+                status,
+            },
+        )?;
+        say!("GET /d/{} {}", token_prefix(token), status);
+    }
+
+    match claim {
+        Claim::Serve { archive, file } => {
+            let mut archive = tokio::fs::File::from_std(archive);
+            let length = archive.metadata().await?.len();
+            let header = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/zip\r\nContent-Length: {}\r\n\
+                 Content-Disposition: attachment; filename=\"{}\"\r\n\
+                 Cache-Control: no-store\r\nConnection: close\r\n\r\n",
+                length,
+                file.replace('"', "")
+            );
+            let sent = async {
+                stream.write_all(header.as_bytes()).await?;
+                tokio::io::copy(&mut archive, &mut stream).await?;
+                stream.flush().await
+            }
+            .await;
+
+            // `Claim::Serve` always comes with a token.
+            let token = token.unwrap_or_default();
+            let mut serving = claims.lock().await;
+            serving.remove(token);
+            if sent.is_err() {
+                match release(dir, token) {
+                    Ok(()) => say!(
+                        "Download of {} was interrupted, the link still works",
+                        token_prefix(token)
+                    ),
+                    Err(e) => say!(
+                        "[WARNING] Failed to restore interrupted download of {}: {}",
+                        token_prefix(token),
+                        e
+                    ),
+                }
+            }
+            drop(serving);
+            sent?;
+        }
+        Claim::NotFound => {
+            respond(&mut stream, "404 Not Found", "This link is not valid.\n").await?;
+        }
+        Claim::Gone => {
+            respond(
+                &mut stream,
+                "410 Gone",
+                "This link has expired or was already used.\n",
+            )
+            .await?;
+        }
+    }
+    stream.shutdown().await?;
+    Ok(())
+}
+
+async fn respond<S>(stream: &mut S, status: &str, body: &str) -> anyhow::Result<()>
+where
+    S: tokio::io::AsyncWrite + Unpin,
+{
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn config(temp: &Path, public_url: &str) -> anyhow::Result<ServeConfig> {
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()])?;
+        std::fs::write(temp.join("cert.pem"), certified.cert.pem())?;
+        std::fs::write(temp.join("key.pem"), certified.signing_key.serialize_pem())?;
+        Ok(ServeConfig {
+            public_url: public_url.to_string(),
+            listen: None,
+            cert: temp.join("cert.pem"),
+            key: temp.join("key.pem"),
+            max_downloads: Some(1),
+            expires_hours: Some(1),
+        })
+    }
+
+    #[test]
+    fn test_sweep_removes_expired_and_used_shares() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let shares = temp_dir.path().join("shares");
+        let archive = temp_dir.path().join("report.pdf.zip");
+        std::fs::write(&archive, b"zip")?;
+        let config = config(temp_dir.path(), "https://files.example.jp/")?;
+
+        let used = publish(&shares, &config, archive.to_str().unwrap(), &[])?;
+        let fresh = publish(&shares, &config, archive.to_str().unwrap(), &[])?;
+        assert!(used.url.starts_with("https://files.example.jp/d/"));
+        let used = used.url.rsplit('/').next().unwrap();
+        let fresh = fresh.url.rsplit('/').next().unwrap();
+
+        let now = Utc::now();
+        assert!(matches!(claim(&shares, used, now)?, Claim::Serve { .. }));
+        assert!(matches!(claim(&shares, used, now)?, Claim::Gone));
+        assert!(matches!(claim(&shares, "../etc", now)?, Claim::NotFound));
+
+        let serving = HashSet::from([used.to_string()]);
+        assert!(sweep(&shares, now, &serving)?.is_empty());
+        assert_eq!(
+            sweep(&shares, now, &HashSet::new())?,
+            vec![used.to_string()]
+        );
+        assert!(shares.join(fresh).exists());
+        assert_eq!(
+            sweep(&shares, now + chrono::Duration::hours(2), &HashSet::new())?,
+            vec![fresh.to_string()]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_released_download_can_be_retried() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let shares = temp_dir.path().join("shares");
+        let archive = temp_dir.path().join("report.pdf.zip");
+        std::fs::write(&archive, b"zip")?;
+        let config = config(temp_dir.path(), "https://files.example.jp")?;
+        let link = publish(&shares, &config, archive.to_str().unwrap(), &[])?;
+        let token = link.url.rsplit('/').next().unwrap();
+
+        let now = Utc::now();
+        assert!(matches!(claim(&shares, token, now)?, Claim::Serve { .. }));
+        release(&shares, token)?;
+        assert!(matches!(claim(&shares, token, now)?, Claim::Serve { .. }));
+        assert!(matches!(claim(&shares, token, now)?, Claim::Gone));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_one_time_download_over_https() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let shares = temp_dir.path().join("shares");
+        let archive = temp_dir.path().join("report.pdf.zip");
+        std::fs::write(&archive, b"encrypted bytes")?;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let public_url = format!("https://localhost:{}", listener.local_addr()?.port());
+        let config = config(temp_dir.path(), &public_url)?;
+        let link = publish(&shares, &config, archive.to_str().unwrap(), &[])?;
+        tokio::spawn(serve(listener, tls_acceptor(&config)?, shares.clone()));
+
+        let cert = std::fs::read(&config.cert)?;
+        let client = reqwest::Client::builder()
+            .add_root_certificate(reqwest::Certificate::from_pem(&cert)?)
+            .build()?;
+        let first = client.get(&link.url).send().await?;
+        assert_eq!(first.status(), 200);
+        assert_eq!(&first.bytes().await?[..], b"encrypted bytes");
+        let second = client.get(&link.url).send().await?;
+        assert_eq!(second.status(), 410);
+
+        let log = std::fs::read_to_string(shares.join(DOWNLOAD_LOG))?;
+        assert!(!log.contains(link.url.rsplit('/').next().unwrap()));
+        let statuses: Vec<u64> = log
+            .lines()
+            .map(|line| {
+                serde_json::from_str::<serde_json::Value>(line).unwrap()["status"]
+                    .as_u64()
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(statuses, vec![200, 410]);
+        Ok(())
+    }
+}
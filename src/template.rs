@@ -0,0 +1,85 @@
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Templates {
+    pub zip_subject: String,
+    pub zip_body: String,
+    pub password_subject: String,
+    pub password_body: String,
+    /// Body of email #1 when the archive is delivered as a download link.
+    pub link_body: String,
+    /// Body of email #1 when the archive is encrypted to the recipients' public keys.
+    pub public_key_body: String,
+}
+
+impl Default for Templates {
+    fn default() -> Self {
+        Self {
+            zip_subject: "Secure File Transfer - Encrypted Document".to_string(),
+            zip_body: "Please find the attached password-protected file.\n\nThe password will be sent in a separate email for security purposes.".to_string(),
+            password_subject: "Secure File Transfer - Password".to_string(),
+            password_body: "Password for the encrypted file: {password}\n\nPlease use this password to open the ZIP file sent in the previous email.".to_string(),
+            link_body: "Please download the password-protected file from the link below.\n\n{link}\n\nThe link expires at {expires}. The password will be sent in a separate email for security purposes.".to_string(),
+            public_key_body: "Please find the attached file, encrypted to your {method} key.\n\nOpen it with your private key. No password will be sent.".to_string(),
+        }
+    }
+}
+
+/// Replaces every `{name}` placeholder with its value. Unknown placeholders are left as-is.
+pub fn render(template: &str, vars: &[(&str, &str)]) -> String {
+    let mut rendered = template.to_string();
+    for (name, value) in vars {
+        rendered = rendered.replace(&format!("{{{}}}", name), value);
+    }
+    rendered
+}
+
+/// Renders a subject, appending `[Ref CODE]` unless the template places `{reference}` itself.
+pub fn subject(template: &str, vars: &[(&str, &str)], reference: &str) -> String {
+    if template.contains("{reference}") {
+        let mut vars = vars.to_vec();
+        vars.push(("reference", reference));
+        render(template, &vars)
+    } else {
+        format!("{} [Ref {}]", render(template, vars), reference)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_replaces_placeholders() {
+        let rendered = render(
+            "Hello {name}, your password is {password}",
+            &[("name", "Taro"), ("password", "secret")],
+        );
+        assert_eq!(rendered, "Hello Taro, your password is secret");
+    }
+
+    #[test]
+    fn test_render_keeps_unknown_placeholders() {
+        let rendered = render("Hello {name}", &[]);
+        assert_eq!(rendered, "Hello {name}");
+    }
+
+    #[test]
+    fn test_subject_carries_reference() {
+        assert_eq!(
+            subject("Files for {name}", &[("name", "Acme")], "K7Q2ZP"),
+            "Files for Acme [Ref K7Q2ZP]"
+        );
+        assert_eq!(
+            subject("#{reference} Password", &[], "K7Q2ZP"),
+            "#K7Q2ZP Password"
+        );
+    }
+
+    #[test]
+    fn test_default_password_body_has_placeholder() {
+        assert!(Templates::default().password_body.contains("{password}"));
+        assert!(Templates::default().link_body.contains("{link}"));
+    }
+}
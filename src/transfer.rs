@@ -0,0 +1,589 @@
+use crate::address;
+use crate::config::Settings;
+use crate::email::config::SendGridConfig;
+use crate::email::sender::{self, EmailSender, Receipt};
+use crate::email::sendgrid::SendGridSender;
+use crate::error::{FailureKind, ResultExt};
+use crate::history::{self, FileDigest};
+use crate::output::say;
+use crate::policy::{self, Envelope};
+use crate::pubkey::{self, Keyring};
+use crate::sanitize;
+use crate::schedule;
+use crate::serve;
+use crate::storage::{Credentials, Link, S3Client};
+use crate::template::{self, Templates};
+use crate::zip::{self, ArchiveFormat, Encryption};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use std::path::Path;
+
+/// How email #1 carries the archive.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Delivery {
+    #[default]
+    Attachment,
+    /// Upload to the `storage` bucket of the profile and send a download link.
+    Link,
+    /// Hand the archive to `ppap serve` and send its download link.
+    Serve,
+}
+
+/// Everything needed to run the PPAP protocol once.
+#[derive(Debug, Clone)]
+pub struct Transfer {
+    pub files: Vec<String>,
+    pub recipients: Vec<String>,
+    pub cc: Vec<String>,
+    /// Receives email #2 instead of `recipients` and `cc` when not empty.
+    pub password_recipients: Vec<String>,
+    pub secure: bool,
+    pub after: Option<u64>,
+    pub templates: Templates,
+    pub format: ArchiveFormat,
+    /// Skips the confirmation for first-time recipients.
+    pub assume_yes: bool,
+    /// Archives sanitized copies of the files instead of the originals.
+    pub strip_metadata: bool,
+    /// Size limit of the recipients' mail gateway; larger archives are split.
+    pub max_message_mb: Option<u64>,
+    pub delivery: Delivery,
+    /// Keys from the address book for `Encryption::Age` and `Encryption::Pgp`.
+    pub public_keys: Keyring,
+}
+
+impl Transfer {
+    /// Recipients of the password email as `(to, cc)`.
+    pub fn password_addresses(&self) -> (&[String], &[String]) {
+        if self.password_recipients.is_empty() {
+            (&self.recipients, &self.cc)
+        } else {
+            (&self.password_recipients, &[])
+        }
+    }
+
+    /// Body of email #1 when the archive is encrypted to public keys.
+    pub fn public_key_body(&self) -> Option<String> {
+        let method = match self.format.encryption {
+            Encryption::Age => "age",
+            Encryption::Pgp => "OpenPGP",
+            _ => return None,
+        };
+        Some(template::render(
+            &self.templates.public_key_body,
+            &[("method", method)],
+        ))
+    }
+
+    /// Validates every address and rewrites it in normalized form.
+    pub fn normalize_addresses(&mut self) -> anyhow::Result<()> {
+        self.recipients = address::normalize(&self.recipients)?;
+        self.cc = address::normalize(&self.cc)?;
+        self.password_recipients = address::normalize(&self.password_recipients)?;
+        Ok(())
+    }
+
+    pub fn envelope(&self, archive_size: u64) -> Envelope<'_> {
+        Envelope {
+            to: &self.recipients,
+            cc: &self.cc,
+            password_to: &self.password_recipients,
+            archive_size,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Report {
+    pub archive_path: String,
+    pub archive: FileDigest,
+    /// The split parts that were sent, or just the archive.
+    pub archive_parts: Vec<String>,
+    /// Download link sent instead of the attachment.
+    pub link: Option<Link>,
+    pub files: Vec<FileDigest>,
+    pub password: String,
+    pub password_scheduled_at: Option<DateTime<Utc>>,
+    pub message_ids: Vec<String>,
+    /// What the provider answered for each email that was sent.
+    pub receipts: Vec<Receipt>,
+    /// Code shown in the subject of both emails so they can be paired.
+    pub reference: String,
+    /// SendGrid only validated the emails, nothing was delivered.
+    pub sandbox: bool,
+    pub metadata_removed: Vec<sanitize::Removed>,
+}
+
+/// Stands in for the download link in sandbox mode, where nothing is uploaded.
+fn sandbox_link(expires_hours: Option<u64>) -> Link {
+    Link {
+        url: "(not uploaded in sandbox mode)".to_string(),
+        expires_at: Utc::now() + chrono::Duration::hours(expires_hours.unwrap_or(72) as i64),
+    }
+}
+
+/// Several files are bundled as `files.zip` in a temporary directory.
+fn bundle_path() -> String {
+    std::env::temp_dir()
+        .join(format!("ppap-{}", std::process::id()))
+        .join("files.zip")
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Sanitized copies are kept apart from the originals until the transfer is done.
+fn sanitized_dir() -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("ppap-{}-clean", std::process::id()))
+}
+
+pub async fn run(settings: &Settings, transfer: &Transfer) -> anyhow::Result<Report> {
+    let templates = &transfer.templates;
+    let recipients = &transfer.recipients;
+    let policy = policy::load(&policy::policy_file_path()).categorize(FailureKind::Config)?;
+    let encryption = transfer.format.encryption;
+    let sandbox = settings.profile.sandbox.unwrap_or(false);
+    let public_keys = if encryption.is_public_key() {
+        let keyring = pubkey::load(&pubkey::keyring_file_path()).categorize(FailureKind::Config)?;
+        let addresses: Vec<String> = recipients.iter().chain(&transfer.cc).cloned().collect();
+        pubkey::resolve(encryption, &addresses, &[&transfer.public_keys, &keyring])
+            .categorize(FailureKind::Config)?
+    } else {
+        Vec::new()
+    };
+
+    let digests = transfer
+        .files
+        .iter()
+        .map(|f| FileDigest::from_path(f))
+        .collect::<anyhow::Result<Vec<_>>>()
+        .categorize(FailureKind::Input)?;
+
+    // Step 1: Scan the files for sensitive data
+    for warning in policy
+        .dlp
+        .enforce(&transfer.files)
+        .categorize(FailureKind::Policy)?
+    {
+        say!("[WARNING] {}", warning);
+    }
+
+    // Step 2: Strip metadata from copies of the files
+    let (files, metadata_removed) = if transfer.strip_metadata {
+        say!("Stripping metadata...");
+        let (copies, removed) = sanitize::sanitized_copies(&transfer.files, &sanitized_dir())
+            .categorize(FailureKind::Input)?;
+        for r in &removed {
+            say!("Removed from {}: {}", r.file, r.items.join(", "));
+        }
+        if removed.is_empty() {
+            say!("No metadata found.");
+        }
+        (copies, removed)
+    } else {
+        (transfer.files.clone(), Vec::new())
+    };
+
+    // Step 3: Compress files and get password
+    say!("Compressing file: {}", transfer.files.join(", "));
+    let password_policy = settings.profile.password_policy();
+    let compressed = match files.as_slice() {
+        [single] if !transfer.strip_metadata => {
+            zip::compress_file(single, &password_policy, &transfer.format)
+        }
+        files => {
+            // A sanitized copy is still archived next to its original.
+            let zip_path = match transfer.files.as_slice() {
+                [original] => format!("{}.zip", original),
+                _ => bundle_path(),
+            };
+            if let Some(parent) = Path::new(&zip_path).parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            zip::compress_files(files, &zip_path, &password_policy, &transfer.format)
+                .map(|password| (zip_path, password))
+        }
+    }
+    .categorize(FailureKind::Archive);
+    if transfer.strip_metadata {
+        let _ = std::fs::remove_dir_all(sanitized_dir());
+    }
+    let (zip_path, password) = compressed?;
+    say!("File compressed successfully: {}", zip_path);
+
+    // Step 4: Encrypt the archive to the recipients' public keys
+    let zip_path = if encryption.is_public_key() {
+        say!("Encrypting archive to {} key(s)...", public_keys.len());
+        pubkey::encrypt(encryption, &zip_path, &public_keys)
+            .inspect_err(|_| {
+                let _ = std::fs::remove_file(&zip_path);
+            })
+            .categorize(FailureKind::Archive)?
+    } else {
+        zip_path
+    };
+    let archive = FileDigest::from_path(&zip_path).categorize(FailureKind::Archive)?;
+
+    let reference = sender::reference_code();
+    let zip_subject = template::subject(&templates.zip_subject, &[], &reference);
+    let mut receipts = Vec::new();
+    let mut parts = vec![zip_path.clone()];
+    let mut link = None;
+    let mut password_scheduled_at = None;
+    // Another command took over the scheduled password email and its history record.
+    let mut handed_off = false;
+    let result: anyhow::Result<()> = async {
+        // Step 5: Scan the files for malware
+        if policy.malware_scan.is_enabled() {
+            say!("\nScanning files for malware...");
+            let infected = policy
+                .malware_scan
+                .scan(&transfer.files)
+                .categorize(FailureKind::Config)?;
+            if !infected.is_empty() {
+                return Err(anyhow::anyhow!(
+                    "Malware found, nothing was sent:\n  {}",
+                    infected.join("\n  ")
+                ))
+                .categorize(FailureKind::Policy);
+            }
+        }
+
+        // Step 6: Check the send policy
+        policy::enforce(
+            &policy,
+            &transfer.envelope(archive.size),
+            transfer.assume_yes,
+            true,
+        )
+        .categorize(FailureKind::Policy)?;
+
+        // Step 7: Initialize SendGrid
+        let config = SendGridConfig::from_env(settings).categorize(FailureKind::Config)?;
+        let mut sender = SendGridSender::new(&config).categorize(FailureKind::Config)?;
+        sender.add_certificates(&transfer.public_keys);
+
+        match transfer.delivery {
+            Delivery::Attachment => {
+                // Step 8: Split the archive if it does not fit in one email
+                let part_size = sender.max_attachment_size(transfer.max_message_mb);
+                parts =
+                    zip::split_archive(&zip_path, part_size).categorize(FailureKind::Archive)?;
+                if parts.len() > 1 {
+                    say!(
+                        "\nArchive is {} bytes, more than {} bytes fit in one email. Split into {} parts.",
+                        archive.size,
+                        part_size,
+                        parts.len()
+                    );
+                }
+
+                // Step 9: Send email #1 with ZIP attachment
+                say!("\nSending email #1: ZIP file attachment");
+                let body = transfer
+                    .public_key_body()
+                    .unwrap_or_else(|| templates.zip_body.clone());
+                sender
+                    .send_archive(
+                        recipients,
+                        &transfer.cc,
+                        &zip_subject,
+                        &body,
+                        &parts,
+                        &sender::reference_headers(&reference),
+                        &mut receipts,
+                    )
+                    .await
+                    .categorize(FailureKind::Delivery)?;
+            }
+            Delivery::Link | Delivery::Serve => {
+                // Step 8: Upload the archive, or hand it to `ppap serve`
+                let missing = |delivery: &str, section: &str| {
+                    anyhow::anyhow!(
+                        "--delivery {} needs a [profiles.NAME.{}] section in {}",
+                        delivery,
+                        section,
+                        settings.config_path.display()
+                    )
+                };
+                // A sandbox run must not leave a working download link behind.
+                let uploaded = if transfer.delivery == Delivery::Link {
+                    let storage = settings
+                        .profile
+                        .storage
+                        .as_ref()
+                        .ok_or_else(|| missing("link", "storage"))
+                        .categorize(FailureKind::Config)?;
+                    let client = Credentials::from_env()
+                        .and_then(|credentials| S3Client::new(storage, credentials))
+                        .categorize(FailureKind::Config)?;
+                    if sandbox {
+                        say!("\nSandbox mode: the archive would be uploaded to {}/{}, nothing was uploaded.", storage.endpoint, storage.bucket);
+                        sandbox_link(storage.expires_hours)
+                    } else {
+                        say!("\nUploading archive...");
+                        client
+                            .upload(&zip_path)
+                            .await
+                            .categorize(FailureKind::Delivery)?
+                    }
+                } else {
+                    let config = settings
+                        .profile
+                        .serve
+                        .as_ref()
+                        .ok_or_else(|| missing("serve", "serve"))
+                        .categorize(FailureKind::Config)?;
+                    if sandbox {
+                        say!("\nSandbox mode: the archive would be published to {}, nothing was published.", serve::shares_dir().display());
+                        sandbox_link(config.expires_hours)
+                    } else {
+                        say!("\nPublishing archive to {}...", serve::shares_dir().display());
+                        serve::publish(&serve::shares_dir(), config, &zip_path, recipients)
+                            .categorize(FailureKind::Delivery)?
+                    }
+                };
+                say!("Download link expires at {}", uploaded.expires_at);
+
+                // Step 9: Send email #1 with the download link
+                say!("\nSending email #1: Download link");
+                let expires = uploaded.expires_at.format("%Y-%m-%d %H:%M UTC").to_string();
+                let body = template::render(
+                    &templates.link_body,
+                    &[("link", &uploaded.url), ("expires", &expires)],
+                );
+                let receipt = sender
+                    .send_email(
+                        recipients,
+                        &transfer.cc,
+                        &zip_subject,
+                        &body,
+                        &sender::reference_headers(&reference),
+                    )
+                    .await
+                    .categorize(FailureKind::Delivery)?;
+                receipts.push(receipt);
+                link = Some(uploaded);
+            }
+        }
+        say!("Email #1 sent successfully!");
+        if encryption.is_public_key() {
+            say!("\nNo password email: the archive is encrypted to the recipients' keys.");
+            return Ok(());
+        }
+
+        // Step 10: Determine which password to send
+        let password_to_send = if transfer.secure {
+            say!("\n[SECURE MODE ACTIVATED]");
+            say!("Generating incorrect password for maximum security...");
+            get_password_to_send(&password, true)
+        } else {
+            password.clone()
+        };
+
+        let password_email_body =
+            template::render(&templates.password_body, &[("password", &password_to_send)]);
+        let password_subject = template::subject(&templates.password_subject, &[], &reference);
+        let (password_to, password_cc) = transfer.password_addresses();
+        let mut headers = sender::reference_headers(&reference);
+        headers.extend(sender::reply_headers(&receipts[0].message_id));
+
+        // Step 11: Handle delayed sending if requested
+        let mut pending = None;
+        if let (Some(hours), true) = (transfer.after, sandbox) {
+            say!("\nSandbox mode: not waiting {} hours for the password email.", hours);
+        } else if let Some(hours) = transfer.after {
+            let send_at = Utc::now() + chrono::Duration::hours(hours as i64);
+            password_scheduled_at = Some(send_at);
+            let mut record = history_record(transfer, &digests, &archive, &reference, &receipts);
+            record.outcome = history::Outcome::PasswordFailed;
+            record.error = Some("Password email was not sent before ppap exited".to_string());
+            let scheduled = pending.insert(schedule::Pending {
+                id: reference.clone(),
+                profile: settings.profile_name.clone(),
+                send_at,
+                to: password_to.to_vec(),
+                cc: password_cc.to_vec(),
+                subject: password_subject.clone(),
+                body: password_email_body.clone(),
+                headers: headers.clone(),
+                record,
+            });
+            schedule::save(&schedule::schedule_dir(), scheduled)
+                .categorize(FailureKind::PasswordDelivery)?;
+            say!("\nPassword email will be sent in {} hours.", hours);
+            say!("Please keep this terminal open. Press Ctrl+C to cancel.");
+            say!(
+                "(File email has already been sent; if this process stops, run `ppap resume {}`)",
+                reference
+            );
+
+            schedule::wait_until(send_at).await;
+            // `ppap schedule cancel` or `ppap resume` may have taken it in the meantime.
+            if !schedule::remove(&schedule::schedule_dir(), &reference)
+                .categorize(FailureKind::PasswordDelivery)?
+            {
+                say!("\nThe password email was cancelled or sent by `ppap resume`, not sending it.");
+                handed_off = true;
+                return Ok(());
+            }
+            say!("\nDelay complete! Sending password email now...");
+        }
+
+        // Step 12: Send email #2 with password, as a reply to email #1
+        say!("\nSending email #2: Password");
+        let sent = sender
+            .send_email(
+                password_to,
+                password_cc,
+                &password_subject,
+                &password_email_body,
+                &headers,
+            )
+            .await;
+        if let (Err(_), Some(pending)) = (&sent, &pending) {
+            // Keep it for `ppap resume`.
+            if let Err(e) = schedule::save(&schedule::schedule_dir(), pending) {
+                say!("[WARNING] Failed to keep the scheduled password email: {}", e);
+            }
+        }
+        let receipt = sent.categorize(FailureKind::PasswordDelivery)?;
+        receipts.push(receipt);
+        say!("Email #2 sent successfully!");
+
+        Ok(())
+    }
+    .await;
+
+    let mut record = history_record(transfer, &digests, &archive, &reference, &receipts);
+    // Any receipt means at least part of the archive reached the recipients.
+    record.outcome = match (&result, !receipts.is_empty()) {
+        (Ok(()), _) => history::Outcome::Delivered,
+        (Err(_), true) => history::Outcome::PasswordFailed,
+        (Err(_), false) => history::Outcome::Failed,
+    };
+    record.error = result.as_ref().err().map(|e| e.to_string());
+    if sandbox {
+        say!("Sandbox mode: the transfer is not recorded in the history.");
+    } else if !handed_off {
+        if let Err(e) = history::append(&history::history_file_path(), &record) {
+            say!("[WARNING] Failed to record transfer history: {}", e);
+        }
+    }
+
+    // Step 13: Cleanup
+    say!("\nCleaning up temporary files...");
+    for part in &parts {
+        if let Err(e) = zip::cleanup_temp_file(part) {
+            say!("[WARNING] Failed to remove {}: {}", part, e);
+        }
+    }
+    if transfer.files.len() > 1 {
+        if let Some(parent) = Path::new(&zip_path).parent() {
+            let _ = std::fs::remove_dir(parent);
+        }
+    }
+    result?;
+
+    Ok(Report {
+        archive_path: zip_path,
+        archive,
+        archive_parts: parts,
+        link,
+        files: digests,
+        password: if encryption.is_public_key() {
+            String::new()
+        } else {
+            password
+        },
+        password_scheduled_at,
+        message_ids: record.message_ids,
+        receipts: record.receipts,
+        reference: record.reference,
+        sandbox,
+        metadata_removed,
+    })
+}
+
+/// History entry of a transfer that got through.
+fn history_record(
+    transfer: &Transfer,
+    digests: &[FileDigest],
+    archive: &FileDigest,
+    reference: &str,
+    receipts: &[Receipt],
+) -> history::TransferRecord {
+    history::TransferRecord {
+        timestamp: Utc::now(),
+        recipients: transfer.recipients.clone(),
+        cc: transfer.cc.clone(),
+        password_recipients: transfer.password_recipients.clone(),
+        files: digests.to_vec(),
+        archive_size: archive.size,
+        message_ids: receipts.iter().map(|r| r.message_id.clone()).collect(),
+        reference: reference.to_string(),
+        receipts: receipts.to_vec(),
+        secure: transfer.secure,
+        outcome: history::Outcome::Delivered,
+        error: None,
+    }
+}
+
+fn get_password_to_send(real_password: &str, secure_mode: bool) -> String {
+    if secure_mode {
+        use rand::distributions::Alphanumeric;
+        use rand::{thread_rng, Rng};
+        thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(real_password.len())
+            .map(char::from)
+            .collect()
+    } else {
+        real_password.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bundle_path_is_in_temp_dir() {
+        let path = bundle_path();
+        assert!(path.ends_with("files.zip"));
+        assert!(Path::new(&path).starts_with(std::env::temp_dir()));
+    }
+
+    #[test]
+    fn test_password_addresses() {
+        let mut transfer = Transfer {
+            files: vec![],
+            recipients: vec!["a@example.com".to_string()],
+            cc: vec!["b@example.com".to_string()],
+            password_recipients: vec![],
+            secure: false,
+            after: None,
+            templates: Templates::default(),
+            format: ArchiveFormat::default(),
+            assume_yes: false,
+            strip_metadata: false,
+            max_message_mb: None,
+            public_keys: Default::default(),
+            delivery: Default::default(),
+        };
+        assert_eq!(transfer.password_addresses().1, ["b@example.com"]);
+
+        transfer.password_recipients = vec!["pw@example.com".to_string()];
+        let (to, cc) = transfer.password_addresses();
+        assert_eq!(to, ["pw@example.com"]);
+        assert!(cc.is_empty());
+    }
+
+    #[test]
+    fn test_secure_mode_password_differs() {
+        let wrong = get_password_to_send("abcdefghijklmnop", true);
+        assert_eq!(wrong.len(), 16);
+        assert_ne!(wrong, "abcdefghijklmnop");
+        assert_eq!(get_password_to_send("abc", false), "abc");
+    }
+}
@@ -0,0 +1,328 @@
+use crate::address::Mailbox;
+use crate::config::Settings;
+use crate::contacts::{self, AddressBook};
+use crate::history::{self, TransferRecord};
+use crate::template::{self, Templates};
+use crate::transfer::{Delivery, Transfer};
+use dialoguer::theme::ColorfulTheme;
+use dialoguer::{Confirm, Input, MultiSelect, Select};
+use std::path::Path;
+
+/// Number of past recipients offered for selection.
+const RECENT_RECIPIENTS: usize = 10;
+
+/// Walks through a transfer interactively. Returns `None` when the user cancels.
+pub fn run(settings: &Settings) -> anyhow::Result<Option<Transfer>> {
+    let theme = ColorfulTheme::default();
+
+    // Step 1: Files
+    let mut files = Vec::new();
+    let candidates = list_files(Path::new("."))?;
+    if !candidates.is_empty() {
+        let picked = MultiSelect::with_theme(&theme)
+            .with_prompt("Files to send (space to select, enter to confirm)")
+            .items(&candidates)
+            .interact()?;
+        files.extend(picked.into_iter().map(|i| candidates[i].clone()));
+    }
+    let extra: String = Input::with_theme(&theme)
+        .with_prompt("Other files (separated by ';', empty to skip)")
+        .allow_empty(true)
+        .validate_with(|input: &String| {
+            match split_list(input)
+                .into_iter()
+                .find(|f| !Path::new(f).is_file())
+            {
+                Some(missing) => Err(format!("File not found: {}", missing)),
+                None => Ok(()),
+            }
+        })
+        .interact_text()?;
+    files.extend(split_list(&extra));
+    if files.is_empty() {
+        return Err(anyhow::anyhow!("No files selected"));
+    }
+
+    let mut transfer = Transfer {
+        files,
+        recipients: Vec::new(),
+        cc: Vec::new(),
+        password_recipients: Vec::new(),
+        secure: false,
+        after: None,
+        templates: settings.profile.templates(),
+        format: Default::default(),
+        assume_yes: false,
+        strip_metadata: settings.profile.strip_metadata.unwrap_or(false),
+        max_message_mb: None,
+        public_keys: Default::default(),
+        delivery: settings.profile.delivery.unwrap_or_default(),
+    };
+
+    // Step 2: Recipients
+    let book = contacts::load(&contacts::contacts_file_path())?;
+    let recent = recent_recipients(&history::read(&history::history_file_path())?);
+    let aliases: Vec<&String> = book.keys().collect();
+    let mut items = contact_labels(&book);
+    items.extend(recent.iter().cloned());
+    let mut preferred_template = None;
+    if !items.is_empty() {
+        let picked = MultiSelect::with_theme(&theme)
+            .with_prompt("Recipients from the address book and recent transfers")
+            .items(&items)
+            .interact()?;
+        for i in picked {
+            match aliases.get(i) {
+                Some(alias) => {
+                    let contact = &book[*alias];
+                    contact.apply(&mut transfer, settings)?;
+                    preferred_template = contact.template.clone().or(preferred_template);
+                }
+                None => transfer.recipients.push(recent[i - aliases.len()].clone()),
+            }
+        }
+    }
+    let extra: String = Input::with_theme(&theme)
+        .with_prompt("Other recipients (separated by ';', empty to skip)")
+        .allow_empty(true)
+        .validate_with(|input: &String| {
+            split_list(input)
+                .iter()
+                .try_for_each(|r| Mailbox::parse(r).map(|_| ()))
+                .map_err(|e| e.to_string())
+        })
+        .interact_text()?;
+    transfer.recipients.extend(split_list(&extra));
+    if transfer.recipients.is_empty() {
+        return Err(anyhow::anyhow!("No recipients selected"));
+    }
+
+    // Step 3: Template, delay and mode
+    let mut names = vec!["default".to_string()];
+    names.extend(settings.templates.keys().cloned());
+    let default = preferred_template
+        .and_then(|name| names.iter().position(|n| *n == name))
+        .unwrap_or(0);
+    transfer.templates = match Select::with_theme(&theme)
+        .with_prompt("Template")
+        .items(&names)
+        .default(default)
+        .interact()?
+    {
+        0 => settings.profile.templates(),
+        i => settings.templates[&names[i]].clone(),
+    };
+    let hours: u64 = Input::with_theme(&theme)
+        .with_prompt("Hours to wait before sending the password (0 to send immediately)")
+        .default(settings.profile.delay_hours.unwrap_or(0))
+        .interact_text()?;
+    transfer.after = (hours > 0).then_some(hours);
+    transfer.secure = Confirm::with_theme(&theme)
+        .with_prompt("Secure mode (send an incorrect password)?")
+        .default(false)
+        .interact()?;
+
+    // Step 4: Preview and confirm
+    println!("\n{}", preview(&transfer));
+    let confirmed = Confirm::with_theme(&theme)
+        .with_prompt("Send these emails?")
+        .default(false)
+        .interact()?;
+    Ok(confirmed.then_some(transfer))
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(';')
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// `@alias (first@example.com, ...)` for every contact, in alias order.
+fn contact_labels(book: &AddressBook) -> Vec<String> {
+    book.iter()
+        .map(|(alias, contact)| format!("@{} ({})", alias, contact.to.join(", ")))
+        .collect()
+}
+
+/// Regular, non-hidden files in `dir`, sorted by name.
+fn list_files(dir: &Path) -> anyhow::Result<Vec<String>> {
+    let mut files: Vec<String> = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_ok_and(|t| t.is_file()))
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| !name.starts_with('.'))
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// Distinct recipients of past transfers, most recent first.
+fn recent_recipients(records: &[TransferRecord]) -> Vec<String> {
+    let mut recipients: Vec<String> = Vec::new();
+    for record in records.iter().rev() {
+        for recipient in &record.recipients {
+            if !recipients.iter().any(|r| r.eq_ignore_ascii_case(recipient)) {
+                recipients.push(recipient.clone());
+            }
+        }
+    }
+    recipients.truncate(RECENT_RECIPIENTS);
+    recipients
+}
+
+fn preview(transfer: &Transfer) -> String {
+    let Templates {
+        zip_subject,
+        zip_body,
+        password_subject,
+        password_body,
+        link_body,
+        ..
+    } = &transfer.templates;
+    let password_body =
+        template::render(password_body, &[("password", "(generated when sending)")]);
+    // The reference code is drawn when sending.
+    let zip_subject = template::subject(zip_subject, &[], "XXXXXX");
+    let password_subject = template::subject(password_subject, &[], "XXXXXX");
+    let timing = match transfer.after {
+        Some(hours) => format!("{} hour(s) after email #1", hours),
+        None => "right after email #1".to_string(),
+    };
+    let addresses = |to: &[String], cc: &[String]| {
+        if cc.is_empty() {
+            format!("To: {}", to.join(", "))
+        } else {
+            format!("To: {}\nCc: {}", to.join(", "), cc.join(", "))
+        }
+    };
+    let (password_to, password_cc) = transfer.password_addresses();
+    let (archive, body) = match transfer.delivery {
+        Delivery::Attachment => (
+            format!("Attachment: {}", transfer.files.join(", ")),
+            transfer
+                .public_key_body()
+                .unwrap_or_else(|| zip_body.clone()),
+        ),
+        Delivery::Link | Delivery::Serve => (
+            format!("Link to: {}", transfer.files.join(", ")),
+            template::render(
+                link_body,
+                &[
+                    ("link", "(uploaded when sending)"),
+                    ("expires", "(set when sending)"),
+                ],
+            ),
+        ),
+    };
+    let email1 = format!(
+        "--- Email #1 ---\n{}\nSubject: {}\n{}\n\n{}\n",
+        addresses(&transfer.recipients, &transfer.cc),
+        zip_subject,
+        archive,
+        body,
+    );
+    if transfer.format.encryption.is_public_key() {
+        return format!(
+            "{}\n--- Email #2 ---\nNot sent: the archive is encrypted to the recipients' public keys.\n",
+            email1
+        );
+    }
+    format!(
+        "{}\n--- Email #2 ({}{}) ---\n{}\nSubject: {}\n\n{}\n",
+        email1,
+        timing,
+        if transfer.secure { ", secure mode" } else { "" },
+        addresses(password_to, password_cc),
+        password_subject,
+        password_body
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::Outcome;
+    use crate::zip::{ArchiveFormat, Encryption};
+    use tempfile::tempdir;
+
+    fn record(recipients: &[&str]) -> TransferRecord {
+        TransferRecord {
+            timestamp: "2026-01-01T00:00:00Z".parse().unwrap(),
+            recipients: recipients.iter().map(|r| r.to_string()).collect(),
+            files: vec![],
+            cc: vec![],
+            password_recipients: vec![],
+            archive_size: 0,
+            message_ids: vec![],
+            reference: String::new(),
+            receipts: vec![],
+            secure: false,
+            outcome: Outcome::Delivered,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn test_recent_recipients() {
+        let records = vec![
+            record(&["a@example.com", "b@example.com"]),
+            record(&["c@example.com"]),
+            record(&["A@example.com"]),
+        ];
+        assert_eq!(
+            recent_recipients(&records),
+            vec!["A@example.com", "c@example.com", "b@example.com"]
+        );
+    }
+
+    #[test]
+    fn test_list_files_skips_hidden_and_directories() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        std::fs::write(temp_dir.path().join("b.pdf"), b"b")?;
+        std::fs::write(temp_dir.path().join("a.xlsx"), b"a")?;
+        std::fs::write(temp_dir.path().join(".env"), b"secret")?;
+        std::fs::create_dir(temp_dir.path().join("dir"))?;
+
+        assert_eq!(list_files(temp_dir.path())?, vec!["a.xlsx", "b.pdf"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_preview_hides_password() {
+        let transfer = Transfer {
+            files: vec!["report.pdf".to_string()],
+            recipients: vec!["a@example.com".to_string()],
+            cc: vec![],
+            password_recipients: vec!["pw@example.com".to_string()],
+            secure: false,
+            after: Some(2),
+            templates: Templates::default(),
+            format: Default::default(),
+            assume_yes: false,
+            strip_metadata: false,
+            max_message_mb: None,
+            public_keys: Default::default(),
+            delivery: Default::default(),
+        };
+        let text = preview(&transfer);
+        assert!(text.contains("(generated when sending)"));
+        assert!(text.contains("2 hour(s) after email #1"));
+        assert!(text.contains("Attachment: report.pdf"));
+        assert!(text.contains("Subject: Secure File Transfer - Password [Ref XXXXXX]\n"));
+        assert!(text.contains("---\nTo: pw@example.com\n"));
+
+        let transfer = Transfer {
+            format: ArchiveFormat {
+                encryption: Encryption::Age,
+                ..Default::default()
+            },
+            ..transfer
+        };
+        let text = preview(&transfer);
+        assert!(text.contains("encrypted to your age key"));
+        assert!(!text.contains("pw@example.com"));
+    }
+}
@@ -1,51 +1,141 @@
 use anyhow::{Context, Result};
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
+use serde::Deserialize;
 use std::fs;
 use std::io::Write;
+use std::path::{Path, PathBuf};
+use zip::unstable::write::FileOptionsExt;
 use zip::write::FileOptions;
 
+const PASSWORD_SYMBOLS: &[u8] = b"!#$%&*+-=?@^_";
+
+/// Shortest `password_length` a profile may ask for.
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+
+#[derive(Debug, Clone)]
+pub struct PasswordPolicy {
+    pub length: usize,
+    pub symbols: bool,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            length: 16,
+            symbols: false,
+        }
+    }
+}
+
+impl PasswordPolicy {
+    pub fn generate(&self) -> String {
+        let mut rng = thread_rng();
+        if !self.symbols {
+            return (&mut rng)
+                .sample_iter(&Alphanumeric)
+                .take(self.length)
+                .map(char::from)
+                .collect();
+        }
+
+        (0..self.length)
+            .map(|_| {
+                if rng.gen_ratio(1, 4) {
+                    char::from(PASSWORD_SYMBOLS[rng.gen_range(0..PASSWORD_SYMBOLS.len())])
+                } else {
+                    char::from(rng.sample(Alphanumeric))
+                }
+            })
+            .collect()
+    }
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Encryption {
+    #[default]
+    Aes,
+    /// Legacy encryption that Windows Explorer can open without extra tools. Weak.
+    #[value(name = "zipcrypto")]
+    ZipCrypto,
+    /// The archive is encrypted to the recipients' age keys instead of a password.
+    Age,
+    /// The archive is encrypted to the recipients' OpenPGP keys with gpg.
+    Pgp,
+}
+
+impl Encryption {
+    /// Encrypted to public keys, so there is no password to send.
+    pub fn is_public_key(self) -> bool {
+        matches!(self, Self::Age | Self::Pgp)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilenameEncoding {
+    #[default]
+    Utf8,
+    /// For recipients whose unzip tools ignore the UTF-8 flag (e.g. older Japanese Windows).
+    ShiftJis,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ArchiveFormat {
+    pub encryption: Encryption,
+    pub filename_encoding: FilenameEncoding,
+}
+
 struct ZipBuilder {
     temp_path: String,
     password: String,
+    format: ArchiveFormat,
 }
 
 impl ZipBuilder {
-    fn new(original_path: &str) -> Self {
-        let temp_path = format!("{}.zip", original_path);
-        let password: String = thread_rng()
-            .sample_iter(&Alphanumeric)
-            .take(16)
-            .map(char::from)
-            .collect();
-
+    fn new(archive_path: String, policy: &PasswordPolicy, format: &ArchiveFormat) -> Self {
         Self {
-            temp_path,
-            password,
+            temp_path: archive_path,
+            password: policy.generate(),
+            format: *format,
         }
     }
 
-    fn create_archive(&self, file_path: &str) -> Result<()> {
-        let file_content =
-            fs::read(file_path).with_context(|| format!("Failed to read file: {}", file_path))?;
-
+    fn create_archive(&self, file_paths: &[String]) -> Result<()> {
         let zip_file =
             fs::File::create(&self.temp_path).with_context(|| "Failed to create zip file")?;
 
         let mut zip = zip::ZipWriter::new(zip_file);
         let options: FileOptions<'_, ()> = FileOptions::default()
             .compression_method(zip::CompressionMethod::Deflated)
-            .with_aes_encryption(zip::AesMode::Aes256, &self.password)
             .unix_permissions(0o755);
+        let options = match self.format.encryption {
+            Encryption::Aes => options.with_aes_encryption(zip::AesMode::Aes256, &self.password),
+            Encryption::ZipCrypto => {
+                options.with_deprecated_encryption(self.password.as_bytes())?
+            }
+            // The whole archive is encrypted afterwards, see `pubkey::encrypt`.
+            Encryption::Age | Encryption::Pgp => options,
+        };
+
+        for file_path in file_paths {
+            let file_content = fs::read(file_path)
+                .with_context(|| format!("Failed to read file: {}", file_path))?;
 
-        let file_name = std::path::Path::new(file_path)
-            .file_name()
-            .and_then(|n| n.to_str())
-            .unwrap_or("file");
+            let file_name = std::path::Path::new(file_path)
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or("file");
 
-        zip.start_file(file_name, options)?;
-        zip.write_all(&file_content)?;
+            zip.start_file(file_name, options)?;
+            zip.write_all(&file_content)?;
+        }
         zip.finish()?;
+
+        if self.format.filename_encoding == FilenameEncoding::ShiftJis {
+            reencode_names(Path::new(&self.temp_path))?;
+        }
         Ok(())
     }
 
@@ -58,15 +148,239 @@ impl ZipBuilder {
     }
 }
 
-pub fn compress_file(file_path: &str) -> Result<(String, String)> {
-    let builder = ZipBuilder::new(file_path);
-    builder.create_archive(file_path)?;
+pub fn compress_file(
+    file_path: &str,
+    policy: &PasswordPolicy,
+    format: &ArchiveFormat,
+) -> Result<(String, String)> {
+    let builder = ZipBuilder::new(format!("{}.zip", file_path), policy, format);
+    if let Err(e) = builder.create_archive(&[file_path.to_string()]) {
+        let _ = fs::remove_file(builder.get_path());
+        return Err(e);
+    }
     Ok((
         builder.get_path().to_string(),
         builder.get_password().to_string(),
     ))
 }
 
+/// Compresses several files into one archive at `archive_path` and returns its password.
+pub fn compress_files(
+    file_paths: &[String],
+    archive_path: &str,
+    policy: &PasswordPolicy,
+    format: &ArchiveFormat,
+) -> Result<String> {
+    let builder = ZipBuilder::new(archive_path.to_string(), policy, format);
+    if let Err(e) = builder.create_archive(file_paths) {
+        let _ = fs::remove_file(builder.get_path());
+        return Err(e);
+    }
+    Ok(builder.get_password().to_string())
+}
+
+const LOCAL_HEADER: u32 = 0x04034b50;
+const CENTRAL_HEADER: u32 = 0x02014b50;
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x06054b50;
+/// General purpose flag bit 11: the file name is UTF-8.
+const UTF8_FLAG: u16 = 1 << 11;
+
+fn read_u16(data: &[u8], at: usize) -> Result<u16> {
+    data.get(at..at + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or_else(|| anyhow::anyhow!("Truncated zip archive"))
+}
+
+fn read_u32(data: &[u8], at: usize) -> Result<u32> {
+    data.get(at..at + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| anyhow::anyhow!("Truncated zip archive"))
+}
+
+/// Rewrites the entry names of a finished archive as Shift_JIS and clears the UTF-8 flag.
+/// The zip crate only writes UTF-8 names, so the headers are rebuilt here.
+fn reencode_names(path: &Path) -> Result<()> {
+    let data = fs::read(path)?;
+    let eocd = (0..data.len().saturating_sub(21))
+        .rev()
+        .find(|&i| read_u32(&data, i).ok() == Some(END_OF_CENTRAL_DIRECTORY))
+        .ok_or_else(|| anyhow::anyhow!("Not a zip archive: {}", path.display()))?;
+    let count = read_u16(&data, eocd + 10)? as usize;
+    let cd_offset = read_u32(&data, eocd + 16)?;
+    if cd_offset == u32::MAX {
+        return Err(anyhow::anyhow!(
+            "Shift_JIS file names are not supported for ZIP64 archives"
+        ));
+    }
+    let cd_offset = cd_offset as usize;
+
+    // (central header offset, local header offset, encoded name)
+    let mut entries = Vec::with_capacity(count);
+    let mut at = cd_offset;
+    for _ in 0..count {
+        if read_u32(&data, at)? != CENTRAL_HEADER {
+            return Err(anyhow::anyhow!("Corrupt central directory"));
+        }
+        let name_len = read_u16(&data, at + 28)? as usize;
+        let extra_len = read_u16(&data, at + 30)? as usize;
+        let comment_len = read_u16(&data, at + 32)? as usize;
+        let local = read_u32(&data, at + 42)? as usize;
+        let name = std::str::from_utf8(&data[at + 46..at + 46 + name_len])?;
+        let (encoded, _, unmappable) = encoding_rs::SHIFT_JIS.encode(name);
+        if unmappable {
+            return Err(anyhow::anyhow!(
+                "File name cannot be encoded as Shift_JIS: {}",
+                name
+            ));
+        }
+        entries.push((at, local, encoded.into_owned()));
+        at += 46 + name_len + extra_len + comment_len;
+    }
+    if at != eocd {
+        return Err(anyhow::anyhow!(
+            "Shift_JIS file names are not supported for ZIP64 archives"
+        ));
+    }
+
+    let mut by_local: Vec<usize> = (0..entries.len()).collect();
+    by_local.sort_by_key(|&i| entries[i].1);
+
+    let mut out = Vec::with_capacity(data.len());
+    let mut new_local = vec![0u32; entries.len()];
+    for (n, &i) in by_local.iter().enumerate() {
+        let (_, local, name) = &entries[i];
+        if read_u32(&data, *local)? != LOCAL_HEADER {
+            return Err(anyhow::anyhow!("Corrupt local file header"));
+        }
+        let name_len = read_u16(&data, local + 26)? as usize;
+        let next = by_local
+            .get(n + 1)
+            .map(|&j| entries[j].1)
+            .unwrap_or(cd_offset);
+
+        new_local[i] = out.len() as u32;
+        let mut header = data[*local..local + 30].to_vec();
+        let flags = read_u16(&header, 6)? & !UTF8_FLAG;
+        header[6..8].copy_from_slice(&flags.to_le_bytes());
+        header[26..28].copy_from_slice(&(name.len() as u16).to_le_bytes());
+        out.extend_from_slice(&header);
+        out.extend_from_slice(name);
+        // Extra field, file data and data descriptor are copied unchanged.
+        out.extend_from_slice(&data[local + 30 + name_len..next]);
+    }
+
+    let new_cd_offset = out.len() as u32;
+    for (i, (central, _, name)) in entries.iter().enumerate() {
+        let name_len = read_u16(&data, central + 28)? as usize;
+        let extra_len = read_u16(&data, central + 30)? as usize;
+        let comment_len = read_u16(&data, central + 32)? as usize;
+
+        let mut header = data[*central..central + 46].to_vec();
+        let flags = read_u16(&header, 8)? & !UTF8_FLAG;
+        header[8..10].copy_from_slice(&flags.to_le_bytes());
+        header[28..30].copy_from_slice(&(name.len() as u16).to_le_bytes());
+        header[42..46].copy_from_slice(&new_local[i].to_le_bytes());
+        out.extend_from_slice(&header);
+        out.extend_from_slice(name);
+        let rest = central + 46 + name_len;
+        out.extend_from_slice(&data[rest..rest + extra_len + comment_len]);
+    }
+    let new_cd_size = out.len() as u32 - new_cd_offset;
+
+    let mut end = data[eocd..].to_vec();
+    end[12..16].copy_from_slice(&new_cd_size.to_le_bytes());
+    end[16..20].copy_from_slice(&new_cd_offset.to_le_bytes());
+    out.extend_from_slice(&end);
+
+    fs::write(path, out)?;
+    Ok(())
+}
+
+/// Cuts the archive into `NAME.zip.001`, `NAME.zip.002`, ... of at most `part_size` bytes,
+/// the split format 7-Zip joins on extraction. The archive is removed once it is split.
+/// Returns the archive itself when it already fits.
+pub fn split_archive(archive_path: &str, part_size: u64) -> Result<Vec<String>> {
+    let data = fs::read(archive_path)
+        .with_context(|| format!("Failed to read archive: {}", archive_path))?;
+    if data.len() as u64 <= part_size {
+        return Ok(vec![archive_path.to_string()]);
+    }
+    if part_size == 0 {
+        return Err(anyhow::anyhow!("Part size must be greater than zero"));
+    }
+
+    let mut parts = Vec::new();
+    for (i, chunk) in data.chunks(part_size as usize).enumerate() {
+        let part = format!("{}.{:03}", archive_path, i + 1);
+        if let Err(e) = fs::write(&part, chunk) {
+            for written in &parts {
+                let _ = fs::remove_file(written);
+            }
+            return Err(e).with_context(|| format!("Failed to write archive part: {}", part));
+        }
+        parts.push(part);
+    }
+    fs::remove_file(archive_path)?;
+    Ok(parts)
+}
+
+/// Reads an archive, joining `NAME.zip.002`, ... when given `NAME.zip.001`.
+fn read_archive(archive_path: &str) -> Result<Vec<u8>> {
+    let mut data = fs::read(archive_path)
+        .with_context(|| format!("Failed to open archive: {}", archive_path))?;
+    if let Some(base) = archive_path.strip_suffix(".001") {
+        for i in 2.. {
+            let part = format!("{}.{:03}", base, i);
+            if !Path::new(&part).exists() {
+                break;
+            }
+            data.extend(
+                fs::read(&part)
+                    .with_context(|| format!("Failed to open archive part: {}", part))?,
+            );
+        }
+    }
+    Ok(data)
+}
+
+/// Extracts every entry into `output_dir` and returns the paths written.
+pub fn extract_archive(
+    archive_path: &str,
+    password: &str,
+    output_dir: &str,
+) -> Result<Vec<PathBuf>> {
+    let data = read_archive(archive_path)?;
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(data))
+        .with_context(|| format!("Failed to read archive: {}", archive_path))?;
+    let output_dir = Path::new(output_dir);
+    fs::create_dir_all(output_dir)?;
+
+    let mut extracted = Vec::new();
+    for i in 0..archive.len() {
+        let mut entry = archive
+            .by_index_decrypt(i, password.as_bytes())
+            .map_err(|e| anyhow::anyhow!("Failed to decrypt entry #{}: {}", i, e))?;
+        let relative = entry
+            .enclosed_name()
+            .ok_or_else(|| anyhow::anyhow!("Unsafe path in archive: {}", entry.name()))?;
+        let out_path = output_dir.join(relative);
+
+        if entry.is_dir() {
+            fs::create_dir_all(&out_path)?;
+            continue;
+        }
+        if let Some(parent) = out_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut out_file = fs::File::create(&out_path)
+            .with_context(|| format!("Failed to create file: {}", out_path.display()))?;
+        std::io::copy(&mut entry, &mut out_file)
+            .with_context(|| "Failed to decrypt archive: wrong password?")?;
+        extracted.push(out_path);
+    }
+    Ok(extracted)
+}
+
 pub fn cleanup_temp_file(temp_path: &str) -> Result<()> {
     fs::remove_file(temp_path).with_context(|| "Failed to remove temporary zip file")?;
     Ok(())
@@ -92,7 +406,11 @@ mod tests {
         let test_content = b"This is a test content that will be encrypted in the ZIP file.";
         let test_file = create_test_file(temp_dir.path(), "secure.txt", test_content)?;
 
-        let (zip_path, password) = compress_file(&test_file)?;
+        let (zip_path, password) = compress_file(
+            &test_file,
+            &PasswordPolicy::default(),
+            &ArchiveFormat::default(),
+        )?;
         println!("Generated password: {}", password);
 
         assert!(std::path::Path::new(&zip_path).exists());
@@ -105,7 +423,11 @@ mod tests {
 
     #[test]
     fn test_file_not_found() {
-        let result = compress_file("nonexistent.txt");
+        let result = compress_file(
+            "nonexistent.txt",
+            &PasswordPolicy::default(),
+            &ArchiveFormat::default(),
+        );
         assert!(result.is_err());
     }
 
@@ -116,7 +438,11 @@ mod tests {
 
         let mut passwords = vec![];
         for _ in 0..5 {
-            let (zip_path, password) = compress_file(&test_file)?;
+            let (zip_path, password) = compress_file(
+                &test_file,
+                &PasswordPolicy::default(),
+                &ArchiveFormat::default(),
+            )?;
             passwords.push(password);
             cleanup_temp_file(&zip_path)?;
         }
@@ -130,4 +456,129 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_extract_archive_roundtrip() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let test_file = create_test_file(temp_dir.path(), "report.txt", b"quarterly numbers")?;
+        let (zip_path, password) = compress_file(
+            &test_file,
+            &PasswordPolicy::default(),
+            &ArchiveFormat::default(),
+        )?;
+
+        let out_dir = temp_dir.path().join("out");
+        let extracted = extract_archive(&zip_path, &password, out_dir.to_str().unwrap())?;
+        assert_eq!(extracted, vec![out_dir.join("report.txt")]);
+        assert_eq!(fs::read(&extracted[0])?, b"quarterly numbers");
+
+        assert!(extract_archive(&zip_path, "wrong-password", out_dir.to_str().unwrap()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_archive_roundtrip() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let file_path = temp_dir.path().join("scan.bin");
+        let content: Vec<u8> = (0..20_000u32).map(|_| thread_rng().gen()).collect();
+        fs::write(&file_path, &content)?;
+
+        let (zip_path, password) = compress_file(
+            file_path.to_str().unwrap(),
+            &PasswordPolicy::default(),
+            &ArchiveFormat::default(),
+        )?;
+        let parts = split_archive(&zip_path, 8_000)?;
+        assert_eq!(parts.len(), 3);
+        assert!(parts[0].ends_with("scan.bin.zip.001"));
+        assert!(!Path::new(&zip_path).exists());
+
+        let out_dir = temp_dir.path().join("out");
+        let extracted = extract_archive(&parts[0], &password, out_dir.to_str().unwrap())?;
+        assert_eq!(fs::read(&extracted[0])?, content);
+
+        assert_eq!(split_archive(&parts[2], 8_000)?, vec![parts[2].clone()]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_compress_multiple_files() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let files = vec![
+            create_test_file(temp_dir.path(), "a.txt", b"first")?,
+            create_test_file(temp_dir.path(), "b.txt", b"second")?,
+        ];
+        let archive = temp_dir.path().join("bundle.zip");
+        let archive = archive.to_str().unwrap();
+
+        let password = compress_files(
+            &files,
+            archive,
+            &PasswordPolicy::default(),
+            &ArchiveFormat::default(),
+        )?;
+
+        let out_dir = temp_dir.path().join("out");
+        let extracted = extract_archive(archive, &password, out_dir.to_str().unwrap())?;
+        assert_eq!(extracted.len(), 2);
+        assert_eq!(fs::read(out_dir.join("b.txt"))?, b"second");
+        Ok(())
+    }
+
+    #[test]
+    fn test_zipcrypto_roundtrip() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let test_file = create_test_file(temp_dir.path(), "legacy.txt", b"for old explorers")?;
+        let format = ArchiveFormat {
+            encryption: Encryption::ZipCrypto,
+            ..Default::default()
+        };
+        let (zip_path, password) = compress_file(&test_file, &PasswordPolicy::default(), &format)?;
+
+        let out_dir = temp_dir.path().join("out");
+        let extracted = extract_archive(&zip_path, &password, out_dir.to_str().unwrap())?;
+        assert_eq!(fs::read(&extracted[0])?, b"for old explorers");
+        Ok(())
+    }
+
+    #[test]
+    fn test_shift_jis_file_names() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let files = vec![
+            create_test_file(temp_dir.path(), "見積書.txt", b"estimate")?,
+            create_test_file(temp_dir.path(), "請求書.txt", b"invoice")?,
+        ];
+        let archive = temp_dir.path().join("bundle.zip");
+        let archive = archive.to_str().unwrap();
+        let format = ArchiveFormat {
+            filename_encoding: FilenameEncoding::ShiftJis,
+            ..Default::default()
+        };
+        let password = compress_files(&files, archive, &PasswordPolicy::default(), &format)?;
+
+        let raw = fs::read(archive)?;
+        let (sjis, _, _) = encoding_rs::SHIFT_JIS.encode("請求書.txt");
+        assert!(raw.windows(sjis.len()).any(|w| w == &*sjis));
+        assert!(!raw.windows(12).any(|w| w == "請求書".as_bytes()));
+
+        // The archive stays readable and decryptable after the headers are rewritten.
+        let out_dir = temp_dir.path().join("out");
+        let extracted = extract_archive(archive, &password, out_dir.to_str().unwrap())?;
+        assert_eq!(extracted.len(), 2);
+        assert_eq!(fs::read(&extracted[1])?, b"invoice");
+        Ok(())
+    }
+
+    #[test]
+    fn test_password_policy_length() {
+        let policy = PasswordPolicy {
+            length: 24,
+            symbols: true,
+        };
+        let password = policy.generate();
+        assert_eq!(password.chars().count(), 24);
+        assert!(password
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || PASSWORD_SYMBOLS.contains(&b)));
+    }
 }